@@ -0,0 +1,67 @@
+use std::{
+    fs::{create_dir_all, read_dir},
+    path::{Path, PathBuf},
+};
+
+use anstream::println;
+use chrono::Utc;
+use eyre::eyre;
+use owo_colors::OwoColorize;
+
+use crate::{db::DatabaseAdapter, error::Result};
+
+/// Extension of backup files, which are named after the time they were taken.
+const EXTENSION: &str = "backup";
+
+/// Back up the database to a new file in `dir`, creating the directory if needed.
+pub fn create(db: &mut Box<dyn DatabaseAdapter>, url: &str, dir: &str) -> Result<PathBuf> {
+    create_dir_all(dir).map_err(|e| eyre!("unable to create {dir}: {e}"))?;
+
+    let name = format!("{}.{EXTENSION}", Utc::now().format("%Y%m%d%H%M%S%3f"));
+    let path = Path::new(dir).join(name);
+
+    db.backup(url, &path)
+        .map_err(|e| eyre!("unable to back up to {}: {e}", path.display()))?;
+
+    println!("{} {}", "Backed up".blue(), path.display());
+
+    Ok(path)
+}
+
+/// The most recent backup in `dir`.
+pub fn latest(dir: &str) -> Result<PathBuf> {
+    read_dir(dir)
+        .map_err(|e| eyre!("unable to read {dir}: {e}"))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .max()
+        .ok_or_else(|| eyre!("no backup found in {dir}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use super::*;
+
+    #[test]
+    fn test_latest() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_str = dir.path().to_str().unwrap();
+
+        assert!(latest(dir_str).is_err());
+
+        for name in [
+            "20260101000000000.backup",
+            "20260102000000000.backup",
+            "notes.txt",
+        ] {
+            write(dir.path().join(name), "").unwrap();
+        }
+
+        assert_eq!(
+            latest(dir_str).unwrap(),
+            dir.path().join("20260102000000000.backup")
+        );
+    }
+}
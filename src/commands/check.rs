@@ -0,0 +1,67 @@
+use anstream::println;
+use clap::Parser;
+use owo_colors::OwoColorize;
+use proc_exit::Code;
+use tracing::instrument;
+
+use crate::{
+    Options,
+    db::get_db_adapter,
+    error::{Result, exit},
+    migration::planner::{MigrationState, Planner, RepeatableState, print_status},
+    output::OutputFormat,
+};
+
+/// Exit code when migrations are pending.
+const PENDING: Code = Code::new(10);
+/// Exit code when applied migrations differ from their local files.
+const VARIANT: Code = Code::new(11);
+/// Exit code when applied migrations are missing locally.
+const DIVERGENT: Code = Code::new(12);
+/// Exit code when a rollup needs to be synced into the database.
+const ROLLUP: Code = Code::new(13);
+
+/// Check whether the database is in sync with the migrations
+///
+/// Exits with 0 when in sync, otherwise with the code of the most severe problem:
+/// 12 for divergent, 11 for variant, 13 for an unsynced rollup and 10 for pending migrations,
+/// including new or changed repeatable migrations.
+#[derive(Debug, Parser)]
+pub struct Check {}
+
+impl Check {
+    #[instrument(name = "check", skip_all)]
+    pub(crate) fn run(&self, opts: &Options) -> Result {
+        let mut db = get_db_adapter(opts, false)?;
+        let planner = Planner::new(opts, &mut db)?;
+
+        let statuses = planner.unapplied_status()?;
+
+        // Removed repeatables leave nothing to apply, they are not a problem
+        let repeatables = planner
+            .repeatable_status()
+            .into_iter()
+            .filter(|s| matches!(s.state, RepeatableState::Pending | RepeatableState::Changed))
+            .collect::<Vec<_>>();
+
+        let has = |state: MigrationState| statuses.iter().any(|s| s.state == state);
+
+        let code = if has(MigrationState::Divergent) {
+            DIVERGENT
+        } else if has(MigrationState::Variant) {
+            VARIANT
+        } else if planner.needs_rollup_sync() {
+            ROLLUP
+        } else if has(MigrationState::Pending) || !repeatables.is_empty() {
+            PENDING
+        } else {
+            println!("{}", "In sync".green());
+
+            return Ok(());
+        };
+
+        print_status(&statuses, &repeatables, OutputFormat::Text, false)?;
+
+        exit(code);
+    }
+}
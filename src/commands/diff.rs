@@ -0,0 +1,117 @@
+use std::fs::read_to_string;
+
+use anstream::println;
+use clap::Parser;
+use eyre::eyre;
+use owo_colors::OwoColorize;
+use proc_exit::Code;
+use similar::{ChangeTag, TextDiff};
+use tracing::instrument;
+
+use crate::{
+    Options,
+    db::get_db_adapter,
+    error::{Result, exit},
+    migration::planner::{PlanOptions, Planner},
+};
+
+/// Exit code when the live schema differs from the expected one.
+const DRIFT: Code = Code::new(30);
+
+/// Compare the schema of the database with the committed schema file
+///
+/// With --shadow-url, the expected schema is instead dumped from a scratch database
+/// after applying every migration to it. Prints a unified diff and exits with 30 on drift.
+#[derive(Debug, Parser)]
+pub struct Diff {
+    /// Scratch database to build from the migrations and compare against
+    #[clap(long, value_name = "URL", env = "SHADOW_DATABASE_URL")]
+    pub shadow_url: Option<String>,
+}
+
+impl Diff {
+    #[instrument(name = "diff", skip_all)]
+    pub(crate) fn run(&self, opts: &Options) -> Result {
+        let url = opts.get_url()?;
+        let mut db = get_db_adapter(opts, false)?;
+
+        let (expected, label, actual) = if let Some(shadow_url) = &self.shadow_url {
+            if shadow_url == url {
+                return Err(eyre!("the shadow database must not be the database itself"));
+            }
+
+            let shadow_opts = Options {
+                url: Some(shadow_url.clone()),
+                ..opts.clone()
+            };
+            let mut shadow = get_db_adapter(&shadow_opts, true)?;
+            shadow.lock(opts.lock_wait())?;
+
+            Planner::new(&shadow_opts, &mut shadow)?
+                .count(None)
+                .up()?
+                .run(&mut shadow, &PlanOptions::default())?;
+
+            // The tracking tables differ by the history they hold, leave them out
+            let expected = shadow.dump_schema(shadow_url, true)?;
+            shadow.unlock()?;
+
+            (
+                expected,
+                String::from("migrations"),
+                db.dump_schema(url, true)?,
+            )
+        } else {
+            let Some(path) = &opts.schema else {
+                return Err(eyre!(
+                    "no schema to compare with, pass --schema or --shadow-url"
+                ));
+            };
+
+            let expected = read_to_string(path)
+                .map_err(|e| eyre!("unable to read schema {path}: {e}"))?
+                .into_bytes();
+
+            (expected, path.clone(), db.dump_schema(url, false)?)
+        };
+
+        let expected = String::from_utf8_lossy(&expected);
+        let actual = String::from_utf8_lossy(&actual);
+
+        if expected == actual {
+            println!("{}", "No drift".green());
+
+            return Ok(());
+        }
+
+        print_diff(&expected, &actual, &label);
+
+        exit(DRIFT);
+    }
+}
+
+/// Print a coloured unified diff from the expected schema to the live one.
+fn print_diff(expected: &str, actual: &str, label: &str) {
+    let diff = TextDiff::from_lines(expected, actual);
+
+    println!("{}", format!("--- {label}").red().bold());
+    println!("{}", "+++ database".green().bold());
+
+    for hunk in diff.unified_diff().iter_hunks() {
+        println!("{}", hunk.header().cyan());
+
+        for change in hunk.iter_changes() {
+            let value = change.value().trim_end_matches('\n');
+
+            match change.tag() {
+                ChangeTag::Delete => println!("{}", format!("-{value}").red()),
+                ChangeTag::Insert => println!("{}", format!("+{value}").green()),
+                ChangeTag::Equal => println!(" {value}"),
+            }
+
+            if change.missing_newline() {
+                println!("\\ No newline at end of file");
+            }
+        }
+    }
+}
@@ -12,17 +12,31 @@ use crate::{
 #[derive(Debug, Parser)]
 pub struct Down {
     /// Number of migrations to rollback
-    #[clap(short, long, default_value_t = 1, conflicts_with = "all")]
+    #[clap(short, long, default_value_t = 1, conflicts_with_all = ["all", "to"])]
     pub number: usize,
 
     /// Rollback all applied migrations
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "to")]
     pub all: bool,
 
+    /// Rollback migrations applied after this one (name or timestamp prefix)
+    #[clap(long)]
+    pub to: Option<String>,
+
     /// Only show the migration plan without rolling back
     #[clap(short, long)]
     pub plan_only: bool,
 
+    /// Write the plan as a SQL script to FILE ("-" for stdout) without rolling back
+    #[clap(long, value_name = "FILE", conflicts_with = "plan_only")]
+    pub script: Option<String>,
+
+    /// Roll back in a single transaction, keeping every migration on failure
+    ///
+    /// Not supported on MySQL, which commits schema changes implicitly.
+    #[clap(long, conflicts_with = "script")]
+    pub atomic: bool,
+
     /// Ignore divergent migrations
     #[clap(long)]
     pub ignore_divergent: bool,
@@ -36,22 +50,29 @@ impl Down {
     #[instrument(name = "down", skip_all)]
     pub(crate) fn run(&self, opts: &Options) -> Result {
         let mut db = get_db_adapter(opts, false)?;
+        db.lock(opts.lock_wait())?;
+
+        let plan_options = PlanOptions {
+            seed: false,
+            plan_only: self.plan_only,
+            script: self.script.clone(),
+            atomic: self.atomic,
+        };
 
-        Planner::new(opts)?
+        Planner::new(opts, &mut db)?
             .set_ignore_divergent(self.ignore_divergent)
             .set_ignore_unreversible(self.ignore_unreversible)
             .count((!self.all).then_some(self.number))
+            .target(self.to.clone())
             .down()?
-            .run(
-                &mut db,
-                &PlanOptions {
-                    seed: false,
-                    plan_only: self.plan_only,
-                },
-            )?;
+            .confirm(opts, &plan_options)?
+            .backup(&mut db, opts, &plan_options)?
+            .run(&mut db, &plan_options)?;
 
         maybe_dump_schema(&mut db, opts)?;
 
+        db.unlock()?;
+
         Ok(())
     }
 }
@@ -19,13 +19,18 @@ impl Fix {
     #[instrument(name = "fix", skip_all)]
     pub(crate) fn run(&self, opts: &Options) -> Result {
         let mut db = get_db_adapter(opts, false)?;
+        db.lock(opts.lock_wait())?;
 
-        Planner::new(opts)?
+        Planner::new(opts, &mut db)?
             .fix()?
+            .confirm(opts, &self.plan_options)?
+            .backup(&mut db, opts, &self.plan_options)?
             .run(&mut db, &self.plan_options)?;
 
         maybe_dump_schema(&mut db, opts)?;
 
+        db.unlock()?;
+
         Ok(())
     }
 }
@@ -23,6 +23,8 @@ impl Init {
         migrations_dir.create()?;
 
         let mut db = get_db_adapter(opts, true)?;
+        db.lock(opts.lock_wait())?;
+
         let up_sql = db.init_up_sql();
 
         let compound_name = String::from("20000101000000_init");
@@ -31,16 +33,14 @@ impl Init {
 
         debug!("created migrations directory {migrations_dir}");
 
-        Planner::new(opts)?.up(&mut db)?.run(
-            &mut db,
-            &PlanOptions {
-                seed: false,
-                plan_only: false,
-            },
-        )?;
+        Planner::new(opts, &mut db)?
+            .up()?
+            .run(&mut db, &PlanOptions::default())?;
 
         maybe_dump_schema(&mut db, opts)?;
 
+        db.unlock()?;
+
         Ok(())
     }
 }
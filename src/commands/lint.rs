@@ -0,0 +1,121 @@
+use anstream::println;
+use clap::Parser;
+use owo_colors::OwoColorize;
+use proc_exit::Code;
+use tracing::instrument;
+
+use crate::{
+    Options,
+    db::get_db_adapter,
+    error::{Result, exit},
+    migration::{
+        dir::get_migrations_dir,
+        lint::{Finding, Level, lint},
+        planner::{MigrationState, Planner},
+    },
+    output::{OutputFormat, print_records},
+    sql::Dialect,
+};
+
+/// Exit code when a rule at the error level is broken.
+const ERRORS: Code = Code::new(20);
+/// Exit code when only warnings are found and --deny-warnings is set.
+const WARNINGS: Code = Code::new(21);
+
+/// Check pending migrations for risky statements
+///
+/// Rule levels are set in the `[lint]` table of crude.toml, and a migration can allow
+/// rules with `lint_allow` in its metadata. Exits with 20 when errors are found, and
+/// with 21 when only warnings are found with --deny-warnings.
+#[derive(Debug, Parser)]
+pub struct Lint {
+    /// Lint every local migration without connecting to the database
+    #[clap(long)]
+    pub all: bool,
+
+    /// Exit with an error on warnings too
+    #[clap(long)]
+    pub deny_warnings: bool,
+
+    /// Output format
+    #[clap(short, long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+}
+
+impl Lint {
+    #[instrument(name = "lint", skip_all)]
+    pub(crate) fn run(&self, opts: &Options) -> Result {
+        let (migrations, dialect) = if self.all {
+            // Without a database the URL scheme, if any, tells the dialect
+            let dialect = opts.url.as_deref().and_then(Dialect::from_url);
+
+            (
+                get_migrations_dir(opts).load()?,
+                dialect.unwrap_or(Dialect::Postgres),
+            )
+        } else {
+            let mut db = get_db_adapter(opts, false)?;
+            let pending = Planner::new(opts, &mut db)?
+                .status()?
+                .into_iter()
+                .filter(|s| s.state == MigrationState::Pending)
+                .map(|s| s.migration)
+                .collect();
+
+            (pending, db.dialect())
+        };
+
+        let findings = migrations
+            .iter()
+            .flat_map(|m| lint(m, dialect, &opts.lint))
+            .collect::<Vec<_>>();
+
+        if self.format == OutputFormat::Text {
+            print_findings(&findings, migrations.len());
+        } else {
+            print_records(&findings, self.format)?;
+        }
+
+        let count = |level: Level| findings.iter().filter(|f| f.level == level).count();
+
+        if count(Level::Error) > 0 {
+            exit(ERRORS);
+        } else if self.deny_warnings && count(Level::Warning) > 0 {
+            exit(WARNINGS);
+        }
+
+        Ok(())
+    }
+}
+
+fn print_findings(findings: &[Finding], migrations: usize) {
+    if findings.is_empty() {
+        println!("{} in {migrations} migrations", "No problems found".green());
+
+        return;
+    }
+
+    for finding in findings {
+        let level = match finding.level {
+            Level::Error => format!("{}", format!("error[{}]", finding.rule).red().bold()),
+            _ => format!("{}", format!("warning[{}]", finding.rule).yellow().bold()),
+        };
+
+        println!("{level}: {}", finding.message.bold());
+        println!("  {} {}", "-->".blue(), finding.location);
+
+        if let Some(snippet) = &finding.snippet {
+            println!("{snippet}");
+        }
+
+        println!();
+    }
+
+    let count = |level: Level| findings.iter().filter(|f| f.level == level).count();
+
+    println!(
+        "{} errors and {} warnings in {migrations} migrations",
+        count(Level::Error),
+        count(Level::Warning)
+    );
+}
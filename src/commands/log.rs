@@ -0,0 +1,44 @@
+use clap::Parser;
+use tracing::instrument;
+
+use crate::{
+    Options, db::get_db_adapter, error::Result, migration::history::print_history,
+    output::OutputFormat,
+};
+
+/// Show the history of migration events recorded in the database
+#[derive(Debug, Parser)]
+pub struct Log {
+    /// Only show the last N events
+    #[clap(short = 'n', long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Only show events of the migration NAME
+    #[clap(long, value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// Output format
+    #[clap(short, long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+}
+
+impl Log {
+    #[instrument(name = "log", skip_all)]
+    pub(crate) fn run(&self, opts: &Options) -> Result {
+        let mut db = get_db_adapter(opts, false)?;
+        let mut history = db.load_history()?;
+
+        if let Some(name) = &self.name {
+            history
+                .retain(|e| &e.name == name || e.name.split_once('_').map(|n| n.1) == Some(name));
+        }
+
+        if let Some(limit) = self.limit {
+            history.drain(..history.len().saturating_sub(limit));
+        }
+
+        print_history(&history, self.format)?;
+
+        Ok(())
+    }
+}
@@ -2,12 +2,17 @@ use clap::Parser;
 
 use crate::{Options, error::Result};
 
+pub mod check;
+pub mod diff;
 pub mod down;
 pub mod fix;
 pub mod init;
+pub mod lint;
+pub mod log;
 pub mod new;
 pub mod redo;
 pub mod repair;
+pub mod restore;
 pub mod retime;
 pub mod rollup;
 pub mod status;
@@ -19,12 +24,17 @@ pub enum Subcommands {
     Init(init::Init),
     New(new::New),
     Status(status::Status),
+    Check(check::Check),
+    Lint(lint::Lint),
+    Diff(diff::Diff),
+    Log(log::Log),
     Up(up::Up),
     Down(down::Down),
     Redo(redo::Redo),
     Fix(fix::Fix),
     Repair(repair::Repair),
     Rollup(rollup::Rollup),
+    Restore(restore::Restore),
     Retime(retime::Retime),
     #[clap(hide = true)]
     Verify(verify::Verify),
@@ -36,12 +46,17 @@ impl Subcommands {
             Self::Init(x) => x.run(opts),
             Self::New(x) => x.run(opts),
             Self::Status(x) => x.run(opts),
+            Self::Check(x) => x.run(opts),
+            Self::Lint(x) => x.run(opts),
+            Self::Diff(x) => x.run(opts),
+            Self::Log(x) => x.run(opts),
             Self::Up(x) => x.run(opts),
             Self::Down(x) => x.run(opts),
             Self::Redo(x) => x.run(opts),
             Self::Fix(x) => x.run(opts),
             Self::Repair(x) => x.run(opts),
             Self::Rollup(x) => x.run(opts),
+            Self::Restore(x) => x.run(opts),
             Self::Retime(x) => x.run(opts),
             Self::Verify(x) => x.run(opts),
         }
@@ -12,6 +12,13 @@ use crate::{Options, error::Result, migration::dir::get_migrations_dir};
 pub struct New {
     /// The name of the migration
     pub name: String,
+
+    /// Create a single `.sql` file with `-- +up`, `-- +down` and `-- +seed` sections
+    ///
+    /// Settings of its migration.toml can go in a `-- +meta` section, written as TOML, and
+    /// the seed of an environment in a `-- +seed <env>` section.
+    #[clap(long)]
+    pub single_file: bool,
 }
 
 impl New {
@@ -29,7 +36,11 @@ impl New {
 
         let compound_name = format!("{}_{}", timestamp.format("%Y%m%d%H%M%S"), self.name);
 
-        migrations_dir.create_migration(&compound_name, None, None)?;
+        if self.single_file {
+            migrations_dir.create_single_file_migration(&compound_name)?;
+        } else {
+            migrations_dir.create_migration(&compound_name, None, None)?;
+        }
 
         println!("{} {}", "Created".green(), compound_name);
 
@@ -12,13 +12,17 @@ use crate::{
 #[derive(Debug, Parser)]
 pub struct Redo {
     /// Number of migrations to redo
-    #[clap(short, long, default_value_t = 1, conflicts_with = "all")]
+    #[clap(short, long, default_value_t = 1, conflicts_with_all = ["all", "to"])]
     pub number: usize,
 
     /// Redo all applied migrations
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "to")]
     pub all: bool,
 
+    /// Redo this migration and all applied after it (name or timestamp prefix)
+    #[clap(long)]
+    pub to: Option<String>,
+
     #[clap(flatten)]
     pub plan_options: PlanOptions,
 
@@ -35,16 +39,22 @@ impl Redo {
     #[instrument(name = "redo", skip_all)]
     pub(crate) fn run(&self, opts: &Options) -> Result {
         let mut db = get_db_adapter(opts, false)?;
+        db.lock(opts.lock_wait())?;
 
-        Planner::new(opts)?
+        Planner::new(opts, &mut db)?
             .set_ignore_divergent(self.ignore_divergent)
             .set_ignore_unreversible(self.ignore_unreversible)
             .count((!self.all).then_some(self.number))
+            .target(self.to.clone())
             .redo()?
+            .confirm(opts, &self.plan_options)?
+            .backup(&mut db, opts, &self.plan_options)?
             .run(&mut db, &self.plan_options)?;
 
         maybe_dump_schema(&mut db, opts)?;
 
+        db.unlock()?;
+
         Ok(())
     }
 }
@@ -4,7 +4,16 @@ use eyre::eyre;
 use owo_colors::OwoColorize;
 use tracing::instrument;
 
-use crate::{Options, db::get_db_adapter, error::Result, migration::dir::get_migrations_dir};
+use crate::{
+    Options,
+    db::get_db_adapter,
+    error::Result,
+    migration::{
+        dir::get_migrations_dir,
+        history::{Event, record},
+    },
+    protect,
+};
 
 /// Repair a variant migration by updating its hash
 #[derive(Debug, Parser)]
@@ -20,16 +29,30 @@ impl Repair {
         let local = migrations_dir.load()?;
 
         let mut db = get_db_adapter(opts, false)?;
+        db.lock(opts.lock_wait())?;
 
         let migration = local
             .into_iter()
             .find(|m| m.compound_name == self.name || m.name == self.name)
             .ok_or_else(|| eyre!("unable to find local migration {}", self.name))?;
 
-        db.update_migration_hash(&migration.compound_name, &migration.hash)?;
+        protect::confirm(
+            opts,
+            &format!("repair the hash of migration {}", migration.compound_name),
+        )?;
+
+        record(
+            &mut db,
+            Event::Repair,
+            &migration.compound_name,
+            Some(&migration.hash),
+            |db| db.update_migration_hash(&migration.compound_name, &migration.hash),
+        )?;
 
         println!("{} {}", "Repaired".purple(), migration.compound_name);
 
+        db.unlock()?;
+
         Ok(())
     }
 }
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use anstream::println;
+use clap::Parser;
+use eyre::eyre;
+use owo_colors::OwoColorize;
+use tracing::instrument;
+
+use crate::{
+    Options, backup,
+    db::{DatabaseAdapter, get_db_adapter, maybe_dump_schema},
+    error::Result,
+    migration::history::{Event, record},
+    protect,
+};
+
+/// Restore the database from the latest backup in --backup-dir
+#[derive(Debug, Parser)]
+pub struct Restore {
+    /// Backup file to restore instead of the latest one
+    pub file: Option<PathBuf>,
+}
+
+impl Restore {
+    #[instrument(name = "restore", skip_all)]
+    pub(crate) fn run(&self, opts: &Options) -> Result {
+        let path = match (&self.file, &opts.backup_dir) {
+            (Some(file), _) => file.clone(),
+            (None, Some(dir)) => backup::latest(dir)?,
+            (None, None) => {
+                return Err(eyre!("no backup to restore, pass --backup-dir or a FILE"));
+            }
+        };
+
+        if !path.is_file() {
+            return Err(eyre!("backup {} does not exist", path.display()));
+        }
+
+        let mut db = get_db_adapter(opts, false)?;
+        db.lock(opts.lock_wait())?;
+
+        protect::confirm(opts, &format!("restore {}", path.display()))?;
+
+        let before = applied_names(&mut db)?;
+
+        record(
+            &mut db,
+            Event::Restore,
+            &path.display().to_string(),
+            None,
+            |db| db.restore(opts.get_url()?, &path),
+        )?;
+
+        let after = applied_names(&mut db)?;
+
+        println!("{} {}", "Restored".purple(), path.display());
+
+        // The tracking table comes from the backup, report how it differs
+        for name in before.iter().filter(|name| !after.contains(name)) {
+            println!("{} - {name}", "Unapplied".yellow());
+        }
+
+        for name in after.iter().filter(|name| !before.contains(name)) {
+            println!("{} - {name}", "Reapplied".green());
+        }
+
+        maybe_dump_schema(&mut db, opts)?;
+
+        db.unlock()?;
+
+        Ok(())
+    }
+}
+
+/// Names of the migrations recorded in the tracking table.
+fn applied_names(db: &mut Box<dyn DatabaseAdapter>) -> Result<Vec<String>> {
+    Ok(db
+        .load_migrations()?
+        .into_iter()
+        .map(|m| m.compound_name)
+        .collect())
+}
@@ -12,8 +12,10 @@ use crate::{
     error::Result,
     migration::{
         dir::get_migrations_dir,
+        history::{Event, record},
         planner::{MigrationState, Planner},
     },
+    protect,
 };
 
 /// Squash all applied migrations into a single baseline migration
@@ -27,9 +29,10 @@ impl Rollup {
         let local = migrations_dir.load()?;
 
         let mut db = get_db_adapter(opts, false)?;
+        db.lock(opts.lock_wait())?;
 
         // Error out if status is not clean
-        if Planner::new(opts)?
+        if Planner::new(opts, &mut db)?
             .status()?
             .iter()
             .any(|s| s.state != MigrationState::Applied)
@@ -39,6 +42,8 @@ impl Rollup {
             ));
         }
 
+        protect::confirm(opts, "roll up all migrations")?;
+
         let url = opts.get_url()?;
 
         // Dump current schema excluding the migrations table
@@ -59,8 +64,16 @@ impl Rollup {
         migrations_dir.create_migration(&compound_name, Some(&up_sql), Some(&seed_sql))?;
 
         // Reset migration history and apply baseline record
-        db.clear_migrations()?;
-        db.record_baseline(&compound_name, &hash)?;
+        record(
+            &mut db,
+            Event::Baseline,
+            &compound_name,
+            Some(&hash),
+            |db| {
+                db.clear_migrations()?;
+                db.record_baseline(&compound_name, &hash)
+            },
+        )?;
 
         // Remove all other migrations including previous rollup
         local
@@ -72,6 +85,8 @@ impl Rollup {
                 migrations_dir.remove_migration(&m.compound_name)
             })?;
 
+        db.unlock()?;
+
         Ok(())
     }
 }
@@ -3,20 +3,36 @@ use tracing::instrument;
 
 use crate::{
     Options,
+    db::get_db_adapter,
     error::Result,
     migration::planner::{Planner, print_status},
+    output::OutputFormat,
 };
 
 /// List all migrations and their status
 #[derive(Debug, Parser)]
-pub struct Status {}
+pub struct Status {
+    /// Output format
+    #[clap(short, long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+
+    /// Show who applied each migration, when, and how long it took
+    #[clap(short, long)]
+    pub long: bool,
+}
 
 impl Status {
     #[instrument(name = "status", skip_all)]
     pub(crate) fn run(&self, opts: &Options) -> Result {
-        let status = Planner::new(opts)?.status()?;
+        let mut db = get_db_adapter(opts, false)?;
+        let planner = Planner::new(opts, &mut db)?;
 
-        print_status(&status);
+        print_status(
+            &planner.status()?,
+            &planner.repeatable_status(),
+            self.format,
+            self.long,
+        )?;
 
         Ok(())
     }
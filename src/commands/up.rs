@@ -12,9 +12,13 @@ use crate::{
 #[derive(Debug, Parser)]
 pub struct Up {
     /// Number of migrations to apply
-    #[clap(short, long)]
+    #[clap(short, long, conflicts_with = "to")]
     pub number: Option<usize>,
 
+    /// Apply pending migrations up to and including this one (name or timestamp prefix)
+    #[clap(long)]
+    pub to: Option<String>,
+
     #[clap(flatten)]
     pub plan_options: PlanOptions,
 }
@@ -23,14 +27,20 @@ impl Up {
     #[instrument(name = "up", skip_all)]
     pub(crate) fn run(&self, opts: &Options) -> Result {
         let mut db = get_db_adapter(opts, true)?;
+        db.lock(opts.lock_wait())?;
 
-        Planner::new(opts)?
+        Planner::new(opts, &mut db)?
             .count(self.number)
-            .up(&mut db)?
+            .target(self.to.clone())
+            .up()?
+            .confirm(opts, &self.plan_options)?
+            .backup(&mut db, opts, &self.plan_options)?
             .run(&mut db, &self.plan_options)?;
 
         maybe_dump_schema(&mut db, opts)?;
 
+        db.unlock()?;
+
         Ok(())
     }
 }
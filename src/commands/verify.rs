@@ -1,8 +1,18 @@
 use anstream::println;
 use clap::Parser;
-use tracing::instrument;
+use tracing::{debug, instrument};
 
-use crate::{Options, db::get_db_adapter, error::Result, migration::dir::get_migrations_dir};
+use crate::{
+    Options,
+    db::get_db_adapter,
+    error::Result,
+    migration::{
+        dir::get_migrations_dir,
+        history::{Event, record},
+        vars::Variables,
+    },
+    protect,
+};
 
 /// Verify a migration by applying up, down, then up again
 #[derive(Debug, Parser)]
@@ -14,25 +24,52 @@ pub struct Verify {
 impl Verify {
     #[instrument(name = "verify", skip_all)]
     pub(crate) fn run(&self, opts: &Options) -> Result {
-        let migrations_dir = get_migrations_dir(opts);
-        let mut local = migrations_dir.load()?;
+        let env = opts.env.as_deref();
+        let vars = Variables::from_options(opts)?;
+        let mut local = Vec::new();
 
-        let mut db = get_db_adapter(opts, true)?;
+        // Run the SQL that `up` would, for the environment and with variables substituted
+        for mut m in get_migrations_dir(opts).load()? {
+            if self.name.as_ref().is_some_and(|n| n != &m.compound_name) {
+                continue;
+            }
+
+            if !m.meta.applies_to(env) {
+                debug!("skipping {} outside of its environments", m.compound_name);
+                continue;
+            }
 
-        if let Some(ref n) = self.name {
-            local.retain(|m| &m.compound_name == n);
+            m.set_environment(env)?;
+            m.substitute(&vars)?;
+            local.push(m);
         }
 
+        let mut db = get_db_adapter(opts, true)?;
+        db.lock(opts.lock_wait())?;
+
+        // Each migration is rolled back in between, dropping what it created
+        protect::confirm(
+            opts,
+            &format!("apply and roll back {} migration(s)", local.len()),
+        )?;
+
         for m in local {
             println!("Verifying {}...", m.compound_name);
 
-            db.run_up_migration(&m)?;
-            db.run_down_migration(&m)?;
-            db.run_up_migration(&m)?;
+            let name = &m.compound_name;
+            let hash = Some(m.hash.as_str());
+
+            record(&mut db, Event::Up, name, hash, |db| db.run_up_migration(&m))?;
+            record(&mut db, Event::Down, name, hash, |db| {
+                db.run_down_migration(&m)
+            })?;
+            record(&mut db, Event::Up, name, hash, |db| db.run_up_migration(&m))?;
 
             println!(" OK");
         }
 
+        db.unlock()?;
+
         Ok(())
     }
 }
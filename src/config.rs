@@ -0,0 +1,217 @@
+use std::{
+    collections::BTreeMap,
+    env::current_dir,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use eyre::eyre;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    Options,
+    error::Result,
+    migration::lint::{Level, Rule},
+};
+
+/// Name of the project configuration file, looked up from the current directory upwards.
+pub const CONFIG_FILE: &str = "crude.toml";
+
+/// Settings of a `crude.toml`, either at the top level or in a profile.
+///
+/// Relative paths are resolved from the directory of the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub url: Option<String>,
+    pub migrations_dir: Option<PathBuf>,
+    pub schema: Option<PathBuf>,
+    pub native_dump: Option<bool>,
+    pub lock_wait: Option<u64>,
+    pub statement_timeout_ms: Option<u64>,
+    pub lock_timeout_ms: Option<u64>,
+    pub lock_retries: Option<u32>,
+    pub env: Option<String>,
+    pub var_file: Option<PathBuf>,
+    /// Ask before destructive changes, see `--protected`.
+    pub protected: Option<bool>,
+    pub backup_dir: Option<PathBuf>,
+    /// Levels of lint rules, by rule name.
+    pub lint: BTreeMap<Rule, Level>,
+}
+
+/// Project configuration: default settings and named profiles overriding them.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Directory of the configuration file.
+    pub dir: PathBuf,
+    pub defaults: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    /// Parse a configuration file, its `[profiles.<name>]` tables hold the profiles.
+    pub fn parse(contents: &str, dir: &Path) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+
+        let profiles = match table.remove("profiles") {
+            Some(profiles) => profiles.try_into()?,
+            None => BTreeMap::new(),
+        };
+
+        Ok(Config {
+            dir: dir.to_path_buf(),
+            defaults: table.try_into()?,
+            profiles,
+        })
+    }
+
+    /// Read the configuration file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents =
+            read_to_string(path).map_err(|e| eyre!("unable to read {}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        Config::parse(&contents, dir).map_err(|e| eyre!("invalid {}: {}", path.display(), e))
+    }
+
+    /// Find the closest `crude.toml` in the current directory or its ancestors.
+    pub fn discover() -> Result<Option<Self>> {
+        let cwd = current_dir()?;
+
+        for dir in cwd.ancestors() {
+            let path = dir.join(CONFIG_FILE);
+
+            if path.is_file() {
+                debug!("using configuration from {}", path.display());
+
+                return Config::load(&path).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Fill the options not given as flags or environment variables, from the
+    /// selected profile first and the defaults second.
+    pub fn apply(&self, mut opts: Options) -> Result<Options> {
+        let profile = match &opts.profile {
+            Some(name) => Some(self.profiles.get(name).ok_or_else(|| {
+                eyre!(
+                    "unknown profile {name}, available: {}",
+                    self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                )
+            })?),
+            None => None,
+        };
+
+        // Profile settings take precedence over the defaults
+        let layers = profile
+            .into_iter()
+            .chain([&self.defaults])
+            .collect::<Vec<_>>();
+        let path = |get: fn(&Settings) -> Option<&PathBuf>| {
+            first(&layers, get).map(|p| self.dir.join(p).display().to_string())
+        };
+
+        opts.url = opts.url.or_else(|| first(&layers, |s| s.url.as_ref()));
+        opts.migrations_dir = opts
+            .migrations_dir
+            .or_else(|| path(|s| s.migrations_dir.as_ref()));
+        opts.schema = opts.schema.or_else(|| path(|s| s.schema.as_ref()));
+        opts.var_file = opts.var_file.or_else(|| path(|s| s.var_file.as_ref()));
+        opts.backup_dir = opts.backup_dir.or_else(|| path(|s| s.backup_dir.as_ref()));
+        opts.native_dump =
+            opts.native_dump || first(&layers, |s| s.native_dump.as_ref()).unwrap_or_default();
+        opts.lock_wait = opts
+            .lock_wait
+            .or_else(|| first(&layers, |s| s.lock_wait.as_ref()));
+        opts.statement_timeout = opts
+            .statement_timeout
+            .or_else(|| first(&layers, |s| s.statement_timeout_ms.as_ref()));
+        opts.lock_timeout = opts
+            .lock_timeout
+            .or_else(|| first(&layers, |s| s.lock_timeout_ms.as_ref()));
+        opts.lock_retries = opts
+            .lock_retries
+            .or_else(|| first(&layers, |s| s.lock_retries.as_ref()));
+        opts.env = opts.env.or_else(|| first(&layers, |s| s.env.as_ref()));
+        opts.protected =
+            opts.protected || first(&layers, |s| s.protected.as_ref()).unwrap_or_default();
+
+        // Rule levels merge, a profile only overrides the rules it sets
+        for layer in layers.iter().rev() {
+            opts.lint.extend(layer.lint.clone());
+        }
+
+        Ok(opts)
+    }
+}
+
+/// The first value set in the layers of settings.
+fn first<T: Clone>(layers: &[&Settings], get: impl Fn(&Settings) -> Option<&T>) -> Option<T> {
+    layers.iter().find_map(|s| get(s)).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r#"
+            migrations_dir = "db/migrations"
+            url = "sqlite://dev.db"
+
+            [lint]
+            drop-table = "warning"
+            rename = "off"
+
+            [profiles.prod]
+            url = "postgres://prod/app"
+            env = "production"
+            protected = true
+
+            [profiles.prod.lint]
+            drop-table = "error"
+            "#,
+            Path::new("/app"),
+        )
+        .unwrap();
+
+        assert_eq!(config.defaults.url.as_deref(), Some("sqlite://dev.db"));
+        assert_eq!(config.profiles["prod"].env.as_deref(), Some("production"));
+
+        let opts = |args: &[&str]| {
+            let opts = Options::parse_from([&["crude"], args].concat());
+            config.apply(opts).unwrap()
+        };
+
+        let dev = opts(&[]);
+        assert_eq!(dev.migrations_dir.as_deref(), Some("/app/db/migrations"));
+        assert_eq!(dev.env, None);
+        assert_eq!(dev.lint[&Rule::DropTable], Level::Warning);
+
+        let prod = opts(&["--profile", "prod"]);
+        assert_eq!(prod.migrations_dir.as_deref(), Some("/app/db/migrations"));
+        assert_eq!(prod.env.as_deref(), Some("production"));
+        assert!(prod.protected);
+        assert_eq!(prod.lint[&Rule::DropTable], Level::Error);
+        assert_eq!(prod.lint[&Rule::Rename], Level::Off);
+
+        let flags = opts(&["--profile", "prod", "--url", "sqlite://x.db", "-d", "m"]);
+        assert_eq!(flags.url.as_deref(), Some("sqlite://x.db"));
+        assert_eq!(flags.migrations_dir.as_deref(), Some("m"));
+
+        let unknown = Options::parse_from(["crude", "--profile", "qa"]);
+        assert!(config.apply(unknown).is_err());
+
+        assert!(Config::parse("urls = \"x\"", Path::new("/app")).is_err());
+        assert!(Config::parse("[profiles.prod]\nurls = \"x\"", Path::new("/app")).is_err());
+        assert!(Config::parse("[lint]\ndrop-tables = \"off\"", Path::new("/app")).is_err());
+    }
+}
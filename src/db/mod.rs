@@ -1,25 +1,47 @@
-use std::{fs::write, thread::sleep, time::Duration};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::write,
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
+use ::mysql::{Conn, Opts};
 use ::postgres::{Client, NoTls};
 use eyre::eyre;
 use native_tls::TlsConnector;
 use postgres_native_tls::MakeTlsConnector;
-use rusqlite::Connection;
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
-use crate::{Options, error::Result, migration::Migration};
+use crate::{
+    Options,
+    error::Result,
+    migration::{Migration, Repeatable, Source, history::HistoryEntry, metadata::METADATA_FILE},
+    sql::{Dialect, autocommit_reason, char_offset, location, snippet, split},
+};
 
+mod mysql;
 mod postgres;
 mod sqlite;
 
+pub use mysql::MysqlAdapter;
 pub use postgres::PostgresAdapter;
-pub use sqlite::SqliteAdapter;
+pub use sqlite::{SqliteAdapter, SqliteUrl};
 
 /// Trait that defines database operations for migrations.
 pub trait DatabaseAdapter {
     /// SQL to initialize the migrations tracking table.
     fn init_up_sql(&self) -> &'static str;
 
+    /// SQL dialect of the database.
+    fn dialect(&self) -> Dialect;
+
+    /// Acquire the migration lock, waiting up to `timeout` if another process holds it.
+    fn lock(&mut self, timeout: Duration) -> Result<()>;
+
+    /// Release the migration lock.
+    fn unlock(&mut self) -> Result<()>;
+
     /// Load applied migrations from the database.
     fn load_migrations(&mut self) -> Result<Vec<Migration>>;
 
@@ -29,6 +51,24 @@ pub trait DatabaseAdapter {
     /// Run a DOWN migration and remove it.
     fn run_down_migration(&mut self, migration: &Migration) -> Result<()>;
 
+    /// Render an UP migration, its seed and its record as a standalone SQL script.
+    fn render_up_migration(&self, migration: &Migration) -> String;
+
+    /// Render a DOWN migration and the removal of its record as a standalone SQL script.
+    fn render_down_migration(&self, migration: &Migration) -> String;
+
+    /// Start a transaction spanning the following migrations, until `commit` or `rollback`.
+    fn begin(&mut self) -> Result<()>;
+
+    /// Commit the transaction started by `begin`.
+    fn commit(&mut self) -> Result<()>;
+
+    /// Roll back the transaction started by `begin`.
+    fn rollback(&mut self) -> Result<()>;
+
+    /// Limit how long statements may run and wait for locks, `None` restores the default.
+    fn set_timeouts(&mut self, statement: Option<Duration>, lock: Option<Duration>) -> Result<()>;
+
     /// Update the hash of a migration.
     fn update_migration_hash(&mut self, name: &str, hash: &str) -> Result<()>;
 
@@ -38,51 +78,525 @@ pub trait DatabaseAdapter {
     /// Record a baseline migration in the tracking table without executing its SQL.
     fn record_baseline(&mut self, name: &str, hash: &str) -> Result<()>;
 
+    /// Render clearing the tracking table and recording a rollup baseline as a standalone
+    /// SQL script.
+    fn render_sync_rollup(&self, rollup: &Migration) -> String;
+
+    /// Append an entry to the migration history, creating its table if needed.
+    fn record_history(&mut self, entry: &HistoryEntry) -> Result<()>;
+
+    /// Load the migration history, oldest first.
+    fn load_history(&mut self) -> Result<Vec<HistoryEntry>>;
+
+    /// Load the latest hash of each applied repeatable migration.
+    fn load_repeatables(&mut self) -> Result<Vec<Repeatable>>;
+
+    /// Run a repeatable migration and record its hash, creating the table if needed.
+    fn run_repeatable(&mut self, repeatable: &Repeatable) -> Result<()>;
+
+    /// Render a repeatable migration and its record as a standalone SQL script.
+    fn render_repeatable(&self, repeatable: &Repeatable) -> String;
+
     /// Dump the database schema and return the output.
     fn dump_schema(&mut self, url: &str, exclude_migrations: bool) -> Result<Vec<u8>>;
 
     /// Dump the database data and return the output.
+    ///
+    /// Rows have no natural order, so they are sorted to keep dumps stable.
     fn dump_data(&mut self, url: &str, exclude_migrations: bool) -> Result<Vec<u8>>;
+
+    /// Write a backup of the whole database to `path`, for `restore`.
+    fn backup(&mut self, url: &str, path: &Path) -> Result<()>;
+
+    /// Replace the database with the backup at `path`, keeping the migration history.
+    fn restore(&mut self, url: &str, path: &Path) -> Result<()>;
 }
 
-/// Build a boxed DatabaseAdapter (Postgres or SQLite) based on the URL.
+/// Build a boxed DatabaseAdapter (Postgres, MySQL or SQLite) based on the URL.
 pub fn get_db_adapter(opts: &Options, wait: bool) -> Result<Box<dyn DatabaseAdapter>> {
     let url = opts.get_url()?;
 
     if url.starts_with("postgres://") || url.starts_with("postgresql://") {
-        let mut attempts = 0;
-
         let tls = MakeTlsConnector::new(TlsConnector::builder().build()?);
 
-        let client = loop {
-            let client = if url.contains("sslmode=require") {
+        let client = connect("postgres", wait, || {
+            if url.contains("sslmode=require") {
                 Client::connect(url, tls.clone())
             } else {
                 Client::connect(url, NoTls)
-            };
+            }
+        })?;
+
+        Ok(Box::new(PostgresAdapter::new(client, opts.native_dump)))
+    } else if url.starts_with("mysql://") || url.starts_with("mariadb://") {
+        // The driver only understands the mysql scheme
+        let opts = Opts::from_url(&url.replacen("mariadb://", "mysql://", 1))?;
+
+        let conn = connect("mysql", wait, || Conn::new(opts.clone()))?;
+
+        Ok(Box::new(MysqlAdapter::new(conn)))
+    } else if url.starts_with("sqlite:") {
+        let conn = SqliteUrl::parse(url)?.open()?;
+
+        Ok(Box::new(SqliteAdapter::new(conn)))
+    } else {
+        Err(eyre!("unsupported database URL: {}", url))
+    }
+}
 
-            match client {
-                Ok(client) => break client,
-                Err(err) => {
-                    attempts += 1;
+/// Connect to a database, retrying for up to a minute if `wait` is set.
+fn connect<T, E>(
+    name: &str,
+    wait: bool,
+    mut f: impl FnMut() -> std::result::Result<T, E>,
+) -> Result<T>
+where
+    E: Into<eyre::Report>,
+{
+    let mut attempts = 0;
 
-                    if !wait || attempts > 60 {
-                        return Err(err.into());
-                    }
+    loop {
+        match f() {
+            Ok(conn) => return Ok(conn),
+            Err(err) => {
+                attempts += 1;
 
-                    trace!("failed to connect to postgres, retrying...");
-                    sleep(Duration::from_secs(1));
+                if !wait || attempts > 60 {
+                    return Err(err.into());
                 }
+
+                trace!("failed to connect to {name}, retrying...");
+                sleep(Duration::from_secs(1));
             }
-        };
+        }
+    }
+}
 
-        Ok(Box::new(PostgresAdapter::new(client)))
-    } else if url.starts_with("sqlite://") {
-        let conn = Connection::open(url)?;
+/// Whether a migration file opts out of running inside a transaction.
+pub fn disables_transaction(sql: &str) -> bool {
+    sql.trim_start()
+        .to_lowercase()
+        .starts_with("-- no-transaction")
+}
 
-        Ok(Box::new(SqliteAdapter::new(conn)))
+/// Error of a single statement, with the 1-based character position of the error in it if known.
+pub(crate) struct StatementError {
+    error: eyre::Report,
+    position: Option<usize>,
+    timeout: Option<Timeout>,
+}
+
+impl StatementError {
+    pub(crate) fn at(error: impl Into<eyre::Report>, position: Option<usize>) -> Self {
+        StatementError {
+            error: error.into(),
+            position,
+            timeout: None,
+        }
+    }
+
+    /// Mark the statement as cancelled by a timeout.
+    pub(crate) fn timed_out(mut self, timeout: Option<Timeout>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Timeout that cancelled a statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timeout {
+    /// The statement ran for longer than the statement timeout.
+    Statement,
+    /// The statement waited for a lock for longer than the lock timeout.
+    Lock,
+}
+
+/// Failure of a migration statement, located in its file.
+#[derive(Debug)]
+pub struct MigrationError {
+    message: String,
+    /// The timeout that cancelled the statement, if any.
+    pub timeout: Option<Timeout>,
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl<E: std::error::Error + Send + Sync + 'static> From<E> for StatementError {
+    fn from(error: E) -> Self {
+        StatementError::at(error, None)
+    }
+}
+
+/// Run the statements of the migration file at `source` one at a time.
+///
+/// A failure points at the file, line and column of the failing statement.
+pub(crate) fn run_statements(
+    name: &str,
+    source: &Source,
+    sql: &str,
+    dialect: Dialect,
+    mut execute: impl FnMut(&str) -> std::result::Result<(), StatementError>,
+) -> Result<()> {
+    for statement in split(sql, dialect) {
+        if let Err(e) = execute(statement.sql) {
+            let offset = statement.offset
+                + e.position
+                    .map_or(0, |position| char_offset(statement.sql, position));
+            let (line, column) = location(sql, offset);
+            let line = line + source.line_offset;
+
+            return Err(MigrationError {
+                message: format!(
+                    "migration {name} failed at {source}:{line}:{column}\n{}\n{:#}",
+                    snippet(sql, offset, source.line_offset),
+                    e.error
+                ),
+                timeout: e.timeout,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the statements of the migration file at `source` run inside a transaction.
+///
+/// `transaction` is the mode set in the `migration.toml`, if any. Otherwise files
+/// starting with `-- no-transaction` opt out, as do files made only of statements
+/// that `autocommit` refuses to run in a transaction. Mixing such statements with
+/// others is an error, as their changes couldn't be rolled back.
+pub(crate) fn in_transaction(
+    name: &str,
+    source: &Source,
+    sql: &str,
+    dialect: Dialect,
+    transaction: Option<bool>,
+    autocommit: impl Fn(&str) -> Option<&'static str>,
+) -> Result<bool> {
+    if transaction == Some(false) || (transaction.is_none() && disables_transaction(sql)) {
+        return Ok(false);
+    }
+
+    let statements = split(sql, dialect);
+    let blocking = statements
+        .iter()
+        .filter_map(|s| autocommit(s.sql).map(|reason| (s, reason)))
+        .collect::<Vec<_>>();
+
+    match blocking.first() {
+        None => Ok(true),
+        Some((statement, reason)) if transaction == Some(true) => {
+            let (line, column) = location(sql, statement.offset);
+            let line = line + source.line_offset;
+
+            Err(eyre!(
+                "migration {name} must run in a transaction according to its {METADATA_FILE}, but {reason} cannot run in one at {source}:{line}:{column}\n{}",
+                snippet(sql, statement.offset, source.line_offset),
+            ))
+        }
+        Some((_, reason)) if blocking.len() == statements.len() => {
+            debug!("running {source} outside a transaction because of {reason}");
+
+            Ok(false)
+        }
+        Some((statement, reason)) => {
+            let (line, column) = location(sql, statement.offset);
+            let line = line + source.line_offset;
+
+            Err(eyre!(
+                "migration {name} mixes {reason}, which cannot run in a transaction, with other statements at {source}:{line}:{column}\n{}\n\
+                move it to a migration of its own, or begin the SQL with `-- no-transaction` (or set `transaction = false` in its {METADATA_FILE}) to run every statement outside a transaction",
+                snippet(sql, statement.offset, source.line_offset),
+            ))
+        }
+    }
+}
+
+/// Render SQL scripts followed by a tracking statement, inside a transaction if requested.
+fn render_section(scripts: &[&str], record: Option<&str>, transaction: bool) -> String {
+    let mut body = scripts
+        .iter()
+        .map(|sql| {
+            let mut sql = sql.trim_end().to_string();
+
+            // A trailing comment would swallow a terminator on the same line
+            if !sql.ends_with(';') {
+                sql.push_str("\n;");
+            }
+
+            sql
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    if let Some(record) = record {
+        body.push_str(&format!("\n\n{record}"));
+    }
+
+    if transaction {
+        format!("BEGIN;\n\n{body}\n\nCOMMIT;\n")
     } else {
-        Err(eyre!("unsupported database URL: {}", url))
+        format!("{body}\n")
+    }
+}
+
+/// Whether a rendered file runs in a transaction, mixed files are left for the database to refuse.
+pub(crate) fn render_in_transaction(
+    sql: &str,
+    dialect: Dialect,
+    transaction: Option<bool>,
+) -> bool {
+    if let Some(transaction) = transaction {
+        return transaction;
+    }
+
+    let statements = split(sql, dialect);
+
+    !disables_transaction(sql)
+        && (statements.is_empty()
+            || statements
+                .iter()
+                .any(|s| autocommit_reason(s.sql, dialect).is_none()))
+}
+
+/// Render an UP migration with the given record statement and its optional seed.
+///
+/// The seed runs before the record, so a failing seed leaves the migration unrecorded.
+fn render_up(migration: &Migration, record: &str, dialect: Dialect) -> String {
+    let up_sql = migration.up_sql.as_deref().unwrap_or_default();
+    let transaction = render_in_transaction(up_sql, dialect, migration.meta.transaction);
+
+    match migration.seed_sql.as_deref() {
+        Some(seed) if transaction => render_section(&[up_sql, seed], Some(record), true),
+        Some(seed) => format!(
+            "{}\n{}",
+            render_section(&[up_sql], None, false),
+            render_section(&[seed], Some(record), true)
+        ),
+        None => render_section(&[up_sql], Some(record), transaction),
+    }
+}
+
+/// Render the statement recording an applied migration with the details `up` records.
+///
+/// `down_sql` is already rendered as a literal. The duration is only known once the
+/// script has run, it is left NULL.
+fn render_insert(
+    table: &str,
+    migration: &Migration,
+    down_sql: &str,
+    quote: fn(&str) -> String,
+) -> String {
+    let execution = Execution::current();
+
+    format!(
+        "INSERT INTO {table}
+(name, hash, down_sql, applied_by, hostname, crude_version, duration_ms, seeded, environment)
+VALUES ({}, {}, {down_sql}, {}, {}, {}, NULL, {}, {});",
+        quote(&migration.compound_name),
+        quote(&migration.hash),
+        quote(&execution.applied_by),
+        quote(&execution.hostname),
+        quote(execution.crude_version),
+        if migration.seed_sql.is_some() {
+            "TRUE"
+        } else {
+            "FALSE"
+        },
+        migration
+            .environment
+            .as_deref()
+            .map_or_else(|| String::from("NULL"), quote),
+    )
+}
+
+/// Render syncing a rollup with the given statement clearing `table`, as `up` does
+/// with `clear_migrations` and `record_baseline`.
+fn render_sync(table: &str, clear: &str, rollup: &Migration, quote: fn(&str) -> String) -> String {
+    let execution = Execution::current();
+    let record = format!(
+        "INSERT INTO {table} (name, hash, applied_by, hostname, crude_version)
+VALUES ({}, {}, {}, {}, {});",
+        quote(&rollup.compound_name),
+        quote(&rollup.hash),
+        quote(&execution.applied_by),
+        quote(&execution.hostname),
+        quote(execution.crude_version),
+    );
+
+    render_section(&[clear], Some(&record), true)
+}
+
+/// Render a repeatable migration with the given record statement.
+fn render_repeat(repeatable: &Repeatable, record: &str, dialect: Dialect) -> String {
+    let sql = repeatable.sql.as_deref().unwrap_or_default();
+
+    render_section(
+        &[sql],
+        Some(record),
+        render_in_transaction(sql, dialect, None),
+    )
+}
+
+/// Render a DOWN migration with the given record statement.
+fn render_down(migration: &Migration, record: &str, dialect: Dialect) -> String {
+    let down_sql = migration.down_sql.as_deref().unwrap_or_default();
+
+    render_section(
+        &[down_sql],
+        Some(record),
+        render_in_transaction(down_sql, dialect, migration.meta.transaction),
+    )
+}
+
+/// Quote a string literal by doubling single quotes.
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Tables of crude in MySQL and SQLite databases, which share them with the application.
+const CRUDE_TABLES: &[&str] = &[
+    "crude_migrations",
+    "crude_lock",
+    "crude_history",
+    "crude_repeatables",
+];
+
+/// Execution details recorded with each migration, added to older tracking tables when missing.
+///
+/// Each column has its type in Postgres and MySQL, then in SQLite.
+const TRACKING_COLUMNS: &[(&str, &str, &str)] = &[
+    ("applied_by", "VARCHAR(255)", "TEXT"),
+    ("hostname", "VARCHAR(255)", "TEXT"),
+    ("crude_version", "VARCHAR(64)", "TEXT"),
+    ("duration_ms", "BIGINT", "INTEGER"),
+    ("seeded", "BOOLEAN", "BOOLEAN"),
+    ("environment", "VARCHAR(255)", "TEXT"),
+];
+
+/// Query loading the applied migrations from `table`, which has the `existing` columns.
+///
+/// Older tracking tables lack the execution details, NULL is selected instead.
+pub(crate) fn select_migrations(table: &str, existing: &[String], dialect: Dialect) -> String {
+    let details = TRACKING_COLUMNS
+        .iter()
+        .map(|(name, kind, _)| {
+            if existing.iter().any(|c| c == name) {
+                name.to_string()
+            } else if dialect == Dialect::Postgres {
+                // An untyped NULL would be read as text
+                format!("NULL::{kind} AS {name}")
+            } else {
+                format!("NULL AS {name}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "SELECT name, hash, down_sql, created_at, updated_at, {details}
+        FROM {table} ORDER BY id ASC"
+    )
+}
+
+/// Statements adding the execution detail columns missing from `table`.
+///
+/// There are none when `existing` is empty, as the table does not exist yet.
+pub(crate) fn add_tracking_columns(
+    table: &str,
+    existing: &[String],
+    dialect: Dialect,
+) -> Vec<String> {
+    if existing.is_empty() {
+        return Vec::new();
+    }
+
+    TRACKING_COLUMNS
+        .iter()
+        .filter(|(name, ..)| !existing.iter().any(|c| c == name))
+        .map(|(name, kind, sqlite_kind)| {
+            let kind = if dialect == Dialect::Sqlite {
+                sqlite_kind
+            } else {
+                kind
+            };
+
+            format!("ALTER TABLE {table} ADD COLUMN {name} {kind}")
+        })
+        .collect()
+}
+
+/// Details about this process, recorded with applied migrations.
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub applied_by: String,
+    pub hostname: String,
+    pub crude_version: &'static str,
+}
+
+impl Execution {
+    /// Describe the current process.
+    pub fn current() -> Self {
+        Execution {
+            applied_by: whoami::username(),
+            hostname: whoami::fallible::hostname().unwrap_or_else(|_| String::from("unknown")),
+            crude_version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+/// Describe this process for the holder of the migration lock.
+pub fn lock_holder() -> String {
+    let execution = Execution::current();
+
+    format!(
+        "{}@{} (pid {})",
+        execution.applied_by,
+        execution.hostname,
+        std::process::id()
+    )
+}
+
+/// Milliseconds elapsed since `start`, as stored in the tracking table.
+pub(crate) fn elapsed_ms(start: Instant) -> i64 {
+    start.elapsed().as_millis().try_into().unwrap_or(i64::MAX)
+}
+
+/// Poll `try_lock` until it acquires the migration lock or `timeout` elapses.
+///
+/// `try_lock` returns `None` once the lock is acquired, otherwise a description of its holder.
+fn wait_for_lock(
+    timeout: Duration,
+    mut try_lock: impl FnMut() -> Result<Option<String>>,
+) -> Result<()> {
+    let start = Instant::now();
+    let mut waiting = false;
+
+    loop {
+        let Some(holder) = try_lock()? else {
+            return Ok(());
+        };
+
+        if start.elapsed() >= timeout {
+            return Err(eyre!(
+                "timed out after {}s waiting for the migration lock held by {holder}",
+                timeout.as_secs(),
+            ));
+        }
+
+        if !waiting {
+            warn!("waiting for the migration lock held by {holder}");
+            waiting = true;
+        }
+
+        sleep(Duration::from_millis(500));
     }
 }
 
@@ -0,0 +1,756 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use chrono::NaiveDateTime;
+use eyre::eyre;
+use mysql::{Conn, TxOpts, Value, prelude::Queryable};
+use regex::Regex;
+
+use crate::{
+    db::{
+        CRUDE_TABLES, DatabaseAdapter, Execution, add_tracking_columns, elapsed_ms, render_down,
+        render_insert, render_repeat, render_sync, render_up, run_statements, select_migrations,
+        wait_for_lock,
+    },
+    error::Result,
+    migration::{Applied, Migration, Repeatable, history::HistoryEntry},
+    sql::Dialect,
+};
+
+/// Expression naming the user lock guarding migrations, locks are server wide.
+const LOCK_NAME: &str = "CONCAT('crude.', DATABASE())";
+
+/// Adapter for MySQL/MariaDB-backed migrations.
+///
+/// MySQL implicitly commits every DDL statement, so a migration that fails
+/// halfway keeps the statements that ran before the failure. Only the record
+/// in the tracking table is guaranteed to be absent in that case.
+pub struct MysqlAdapter {
+    conn: Conn,
+    /// Whether the tracking table has every execution detail column, see `lock`.
+    tracking_ready: bool,
+}
+
+impl MysqlAdapter {
+    /// Wrap a `mysql::Conn` as a migrator.
+    pub fn new(conn: Conn) -> Self {
+        MysqlAdapter {
+            conn,
+            tracking_ready: false,
+        }
+    }
+}
+
+impl DatabaseAdapter for MysqlAdapter {
+    fn init_up_sql(&self) -> &'static str {
+        INIT_UP_SQL
+    }
+
+    fn dialect(&self) -> Dialect {
+        Dialect::Mysql
+    }
+
+    fn lock(&mut self, timeout: Duration) -> Result<()> {
+        let conn = &mut self.conn;
+
+        wait_for_lock(timeout, || {
+            let acquired: Option<Option<i64>> =
+                conn.query_first(format!("SELECT GET_LOCK({LOCK_NAME}, 0)"))?;
+
+            if acquired.flatten() == Some(1) {
+                return Ok(None);
+            }
+
+            let holder: Option<(u64, String, String)> = conn.query_first(format!(
+                "SELECT p.id, p.user, p.host
+                FROM information_schema.processlist p
+                WHERE p.id = IS_USED_LOCK({LOCK_NAME})"
+            ))?;
+
+            Ok(Some(holder.map_or_else(
+                || String::from("another connection"),
+                |(id, user, host)| {
+                    format!("another connection (id {id}, user {user}, host {host})")
+                },
+            )))
+        })?;
+
+        // Upgrade an older tracking table once, ALTER TABLE would commit a migration halfway
+        self.tracking_ready = upgrade_tracking_table(&mut self.conn)?;
+
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> Result<()> {
+        self.conn
+            .query_drop(format!("DO RELEASE_LOCK({LOCK_NAME})"))?;
+
+        Ok(())
+    }
+
+    fn load_migrations(&mut self) -> Result<Vec<Migration>> {
+        let table_exists: Option<i64> = self.conn.query_first(
+            "SELECT COUNT(*) FROM information_schema.tables
+            WHERE table_schema = DATABASE()
+            AND table_name = 'crude_migrations'",
+        )?;
+
+        if table_exists.unwrap_or(0) == 0 {
+            return Ok(Vec::new());
+        }
+
+        let existing = tracking_columns(&mut self.conn)?;
+        let rows: Vec<mysql::Row> = self.conn.query(select_migrations(
+            "crude_migrations",
+            &existing,
+            Dialect::Mysql,
+        ))?;
+
+        let mut migrations = Vec::new();
+
+        for row in rows {
+            let name: String = row.get(0).unwrap_or_default();
+            let hash: String = row.get(1).unwrap_or_default();
+            let down_sql: Option<String> = row.get(2).flatten();
+
+            let mut migration = Migration::from_db(name, hash, down_sql)?;
+
+            migration.applied = Some(Applied {
+                applied_at: row.get::<Option<NaiveDateTime>, _>(3).flatten(),
+                updated_at: row.get::<Option<NaiveDateTime>, _>(4).flatten(),
+                applied_by: row.get(5).flatten(),
+                hostname: row.get(6).flatten(),
+                crude_version: row.get(7).flatten(),
+                duration_ms: row.get(8).flatten(),
+                seeded: row.get(9).flatten(),
+                environment: row.get(10).flatten(),
+            });
+
+            migrations.push(migration);
+        }
+
+        Ok(migrations)
+    }
+
+    fn run_up_migration(&mut self, migration: &Migration) -> Result<()> {
+        let name = &migration.compound_name;
+        let hash = &migration.hash;
+        let up_sql = migration.up_sql.as_ref().unwrap();
+        let down_sql = migration.down_sql.as_deref();
+        let seed_sql = migration.seed_sql.as_deref();
+        let execution = Execution::current();
+
+        // DDL commits implicitly, the transaction only protects DML and the record
+        let mut tx = self.conn.start_transaction(TxOpts::default())?;
+
+        let start = Instant::now();
+        run_statements(
+            name,
+            &migration.source("up.sql"),
+            up_sql,
+            Dialect::Mysql,
+            |sql| Ok(tx.query_drop(sql)?),
+        )
+        .map_err(partial_failure)?;
+        let duration_ms = elapsed_ms(start);
+
+        // The tracking table may have been created by this very migration
+        if !self.tracking_ready {
+            upgrade_tracking_table(&mut tx)?;
+        }
+
+        tx.exec_drop(
+            "INSERT INTO crude_migrations
+            (name, hash, down_sql, applied_by, hostname, crude_version, duration_ms, seeded, environment)
+            VALUES (?, ?, ?, ?, ?, ?, ?, FALSE, ?)",
+            (
+                name,
+                hash,
+                down_sql,
+                &execution.applied_by,
+                &execution.hostname,
+                execution.crude_version,
+                duration_ms,
+                &migration.environment,
+            ),
+        )?;
+        tx.commit()?;
+
+        self.tracking_ready = true;
+
+        // always run seed in its own transaction if provided
+        if let Some(seed) = seed_sql {
+            let mut tx = self.conn.start_transaction(TxOpts::default())?;
+            run_statements(
+                name,
+                &migration.source(&migration.seed_file),
+                seed,
+                Dialect::Mysql,
+                |sql| Ok(tx.query_drop(sql)?),
+            )?;
+            tx.exec_drop(
+                "UPDATE crude_migrations SET seeded = TRUE WHERE name = ?",
+                (name,),
+            )?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    fn run_down_migration(&mut self, migration: &Migration) -> Result<()> {
+        let name = &migration.compound_name;
+        let down_sql = migration.down_sql.as_ref().unwrap();
+
+        let mut tx = self.conn.start_transaction(TxOpts::default())?;
+
+        run_statements(
+            name,
+            &migration.source("down.sql"),
+            down_sql,
+            Dialect::Mysql,
+            |sql| Ok(tx.query_drop(sql)?),
+        )
+        .map_err(partial_failure)?;
+        tx.exec_drop("DELETE FROM crude_migrations WHERE name = ?", (name,))?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn render_up_migration(&self, migration: &Migration) -> String {
+        render_up(migration, &render_record(migration), Dialect::Mysql)
+    }
+
+    fn render_down_migration(&self, migration: &Migration) -> String {
+        let record = format!(
+            "DELETE FROM crude_migrations WHERE name = {};",
+            quote_literal(&migration.compound_name)
+        );
+
+        render_down(migration, &record, Dialect::Mysql)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        Err(eyre!(
+            "MySQL commits schema changes implicitly, plans cannot run in a single transaction"
+        ))
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.conn.query_drop("COMMIT")?;
+
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.conn.query_drop("ROLLBACK")?;
+
+        Ok(())
+    }
+
+    fn set_timeouts(&mut self, statement: Option<Duration>, lock: Option<Duration>) -> Result<()> {
+        // MySQL only applies max_execution_time to SELECT statements
+        let millis =
+            statement.map_or_else(|| String::from("DEFAULT"), |t| t.as_millis().to_string());
+        // Lock timeouts are in whole seconds
+        let secs = lock.map_or_else(
+            || String::from("DEFAULT"),
+            |t| t.as_millis().div_ceil(1000).max(1).to_string(),
+        );
+
+        self.conn.query_drop(format!(
+            "SET SESSION max_execution_time = {millis}, \
+            SESSION lock_wait_timeout = {secs}, \
+            SESSION innodb_lock_wait_timeout = {secs}"
+        ))?;
+
+        Ok(())
+    }
+
+    fn update_migration_hash(&mut self, name: &str, hash: &str) -> Result<()> {
+        self.conn.exec_drop(
+            "UPDATE crude_migrations SET hash = ?, updated_at = CURRENT_TIMESTAMP WHERE name = ?",
+            (hash, name),
+        )?;
+
+        Ok(())
+    }
+
+    fn clear_migrations(&mut self) -> Result<()> {
+        // MySQL does not allow selecting from the table being deleted from
+        let min_id: Option<i64> = self
+            .conn
+            .query_first("SELECT MIN(id) FROM crude_migrations")?;
+
+        if let Some(min_id) = min_id {
+            self.conn
+                .exec_drop("DELETE FROM crude_migrations WHERE id > ?", (min_id,))?;
+        }
+
+        Ok(())
+    }
+
+    fn record_baseline(&mut self, name: &str, hash: &str) -> Result<()> {
+        let execution = Execution::current();
+
+        if !self.tracking_ready {
+            self.tracking_ready = upgrade_tracking_table(&mut self.conn)?;
+        }
+
+        self.conn.exec_drop(
+            "INSERT INTO crude_migrations (name, hash, applied_by, hostname, crude_version)
+            VALUES (?, ?, ?, ?, ?)",
+            (
+                name,
+                hash,
+                &execution.applied_by,
+                &execution.hostname,
+                execution.crude_version,
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    fn render_sync_rollup(&self, rollup: &Migration) -> String {
+        // MySQL does not allow selecting from the table being deleted from, unless
+        // through a derived table
+        render_sync(
+            "crude_migrations",
+            "DELETE FROM crude_migrations
+WHERE id > (SELECT id FROM (SELECT MIN(id) AS id FROM crude_migrations) AS m);",
+            rollup,
+            quote_literal,
+        )
+    }
+
+    fn record_history(&mut self, entry: &HistoryEntry) -> Result<()> {
+        self.conn.query_drop(HISTORY_TABLE_SQL)?;
+
+        self.conn.exec_drop(
+            "INSERT INTO crude_history
+            (event, name, hash, success, error, applied_by, hostname, crude_version, duration_ms)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            (
+                entry.event.as_str(),
+                &entry.name,
+                &entry.hash,
+                entry.success,
+                &entry.error,
+                &entry.applied_by,
+                &entry.hostname,
+                &entry.crude_version,
+                entry.duration_ms,
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    fn load_history(&mut self) -> Result<Vec<HistoryEntry>> {
+        let table_exists: Option<i64> = self.conn.query_first(
+            "SELECT COUNT(*) FROM information_schema.tables
+            WHERE table_schema = DATABASE()
+            AND table_name = 'crude_history'",
+        )?;
+
+        if table_exists.unwrap_or(0) == 0 {
+            return Ok(Vec::new());
+        }
+
+        let rows: Vec<mysql::Row> = self.conn.query(
+            "SELECT recorded_at, event, name, hash, success, error,
+                applied_by, hostname, crude_version, duration_ms
+            FROM crude_history ORDER BY id ASC",
+        )?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(HistoryEntry {
+                    recorded_at: row.get::<Option<NaiveDateTime>, _>(0).flatten(),
+                    event: row.get::<String, _>(1).unwrap_or_default().parse()?,
+                    name: row.get(2).unwrap_or_default(),
+                    hash: row.get(3).flatten(),
+                    success: row.get(4).unwrap_or_default(),
+                    error: row.get(5).flatten(),
+                    applied_by: row.get(6).flatten(),
+                    hostname: row.get(7).flatten(),
+                    crude_version: row.get(8).flatten(),
+                    duration_ms: row.get(9).flatten(),
+                })
+            })
+            .collect()
+    }
+
+    fn load_repeatables(&mut self) -> Result<Vec<Repeatable>> {
+        let table_exists: Option<i64> = self.conn.query_first(
+            "SELECT COUNT(*) FROM information_schema.tables
+            WHERE table_schema = DATABASE()
+            AND table_name = 'crude_repeatables'",
+        )?;
+
+        if table_exists.unwrap_or(0) == 0 {
+            return Ok(Vec::new());
+        }
+
+        let rows: Vec<mysql::Row> = self.conn.query(
+            "SELECT name, hash, applied_at, applied_by, hostname, crude_version, duration_ms
+            FROM crude_repeatables ORDER BY name ASC",
+        )?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                Repeatable::from_db(
+                    row.get(0).unwrap_or_default(),
+                    row.get(1).unwrap_or_default(),
+                    Applied {
+                        applied_at: row.get::<Option<NaiveDateTime>, _>(2).flatten(),
+                        updated_at: None,
+                        applied_by: row.get(3).flatten(),
+                        hostname: row.get(4).flatten(),
+                        crude_version: row.get(5).flatten(),
+                        duration_ms: row.get(6).flatten(),
+                        seeded: None,
+                        environment: None,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    fn run_repeatable(&mut self, repeatable: &Repeatable) -> Result<()> {
+        let name = repeatable.label();
+        let sql = repeatable.sql.as_ref().unwrap();
+        let execution = Execution::current();
+
+        // CREATE TABLE commits implicitly, create it before the migration starts
+        self.conn.query_drop(REPEATABLES_TABLE_SQL)?;
+
+        let mut tx = self.conn.start_transaction(TxOpts::default())?;
+
+        let start = Instant::now();
+        run_statements(&name, &repeatable.source(), sql, Dialect::Mysql, |sql| {
+            Ok(tx.query_drop(sql)?)
+        })
+        .map_err(partial_failure)?;
+        let duration_ms = elapsed_ms(start);
+
+        // The tracking table may have been created by this very migration
+        if !self.tracking_ready {
+            upgrade_tracking_table(&mut tx)?;
+        }
+
+        tx.exec_drop(
+            "INSERT INTO crude_repeatables
+            (name, hash, applied_by, hostname, crude_version, duration_ms)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE
+                hash = VALUES(hash),
+                applied_at = CURRENT_TIMESTAMP,
+                applied_by = VALUES(applied_by),
+                hostname = VALUES(hostname),
+                crude_version = VALUES(crude_version),
+                duration_ms = VALUES(duration_ms)",
+            (
+                &repeatable.name,
+                &repeatable.hash,
+                &execution.applied_by,
+                &execution.hostname,
+                execution.crude_version,
+                duration_ms,
+            ),
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn render_repeatable(&self, repeatable: &Repeatable) -> String {
+        let record = format!(
+            "{REPEATABLES_TABLE_SQL}\n\
+            INSERT INTO crude_repeatables (name, hash) VALUES ({}, {})\n\
+            ON DUPLICATE KEY UPDATE hash = VALUES(hash), applied_at = CURRENT_TIMESTAMP;",
+            quote_literal(&repeatable.name),
+            quote_literal(&repeatable.hash),
+        );
+
+        render_repeat(repeatable, &record, Dialect::Mysql)
+    }
+
+    fn dump_schema(&mut self, _url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+
+        for (name, kind) in self.tables(exclude_migrations)? {
+            let statement = if kind == "VIEW" {
+                let row: Option<(String, String, String, String)> = self
+                    .conn
+                    .query_first(format!("SHOW CREATE VIEW {}", quote_ident(&name)))?;
+
+                row.map(|r| r.1).unwrap_or_default()
+            } else {
+                let row: Option<(String, String)> = self
+                    .conn
+                    .query_first(format!("SHOW CREATE TABLE {}", quote_ident(&name)))?;
+
+                row.map(|r| r.1).unwrap_or_default()
+            };
+
+            out.push(clean_create_statement(&statement));
+        }
+
+        let triggers: Vec<String> = self.conn.query(
+            "SELECT trigger_name FROM information_schema.triggers
+            WHERE trigger_schema = DATABASE()
+            ORDER BY trigger_name",
+        )?;
+
+        for name in triggers {
+            let row: Option<mysql::Row> = self
+                .conn
+                .query_first(format!("SHOW CREATE TRIGGER {}", quote_ident(&name)))?;
+
+            if let Some(statement) = row.and_then(|r| r.get::<String, _>(2)) {
+                out.push(clean_create_statement(&statement));
+            }
+        }
+
+        let routines: Vec<(String, String)> = self.conn.query(
+            "SELECT routine_type, routine_name FROM information_schema.routines
+            WHERE routine_schema = DATABASE()
+            ORDER BY routine_type, routine_name",
+        )?;
+
+        for (kind, name) in routines {
+            let row: Option<mysql::Row> = self
+                .conn
+                .query_first(format!("SHOW CREATE {kind} {}", quote_ident(&name)))?;
+
+            if let Some(statement) = row.and_then(|r| r.get::<Option<String>, _>(2)).flatten() {
+                out.push(clean_create_statement(&statement));
+            }
+        }
+
+        Ok(out
+            .into_iter()
+            .map(|s| format!("{s};\n"))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into_bytes())
+    }
+
+    fn dump_data(&mut self, _url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
+        let mut out = String::new();
+
+        for (name, kind) in self.tables(exclude_migrations)? {
+            if kind == "VIEW" {
+                continue;
+            }
+
+            let table = quote_ident(&name);
+
+            let mut rows =
+                self.conn
+                    .query_map(format!("SELECT * FROM {table}"), |row: mysql::Row| {
+                        row.unwrap()
+                            .iter()
+                            .map(|v: &Value| v.as_sql(false))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    })?;
+
+            rows.sort();
+
+            for row in rows {
+                out.push_str(&format!("INSERT INTO {table} VALUES ({row});\n"));
+            }
+        }
+
+        Ok(out.into_bytes())
+    }
+
+    fn backup(&mut self, _url: &str, _path: &Path) -> Result<()> {
+        Err(eyre!("backups are not supported for MySQL"))
+    }
+
+    fn restore(&mut self, _url: &str, _path: &Path) -> Result<()> {
+        Err(eyre!("backups are not supported for MySQL"))
+    }
+}
+
+impl MysqlAdapter {
+    /// List tables and views in the current database, sorted by name.
+    fn tables(&mut self, exclude_migrations: bool) -> Result<Vec<(String, String)>> {
+        let tables: Vec<(String, String)> = self.conn.query(
+            "SELECT table_name, table_type FROM information_schema.tables
+            WHERE table_schema = DATABASE()
+            ORDER BY table_type, table_name",
+        )?;
+
+        Ok(tables
+            .into_iter()
+            .filter(|(name, _)| !exclude_migrations || !CRUDE_TABLES.contains(&name.as_str()))
+            .collect())
+    }
+}
+
+/// Render the record of an applied migration for a standalone SQL script.
+fn render_record(migration: &Migration) -> String {
+    let down_sql = migration
+        .down_sql
+        .as_deref()
+        .map_or_else(|| String::from("NULL"), quote_literal);
+
+    render_insert("crude_migrations", migration, &down_sql, quote_literal)
+}
+
+/// Columns of the tracking table, used to detect tables created by older versions.
+fn tracking_columns(conn: &mut impl Queryable) -> Result<Vec<String>> {
+    let columns: Vec<String> = conn.query(
+        "SELECT column_name FROM information_schema.columns
+        WHERE table_schema = DATABASE()
+        AND table_name = 'crude_migrations'",
+    )?;
+
+    Ok(columns)
+}
+
+/// Add execution detail columns missing from the tracking table, returning whether it exists.
+fn upgrade_tracking_table(conn: &mut impl Queryable) -> Result<bool> {
+    let existing = tracking_columns(conn)?;
+
+    for sql in add_tracking_columns("crude_migrations", &existing, Dialect::Mysql) {
+        conn.query_drop(sql)?;
+    }
+
+    Ok(!existing.is_empty())
+}
+
+/// Explain what a failed MySQL migration leaves behind.
+fn partial_failure(err: eyre::Report) -> eyre::Report {
+    eyre::eyre!(
+        "{err}\n\
+        MySQL commits DDL implicitly, statements before the failing one were \
+        not rolled back and the migration was not recorded"
+    )
+}
+
+/// Quote a string literal, MySQL also treats backslashes as escapes.
+fn quote_literal(value: &str) -> String {
+    Value::from(value).as_sql(false)
+}
+
+/// Quote an identifier with backticks.
+fn quote_ident(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+/// Remove server specific details from `SHOW CREATE` output.
+fn clean_create_statement(statement: &str) -> String {
+    let re_auto_increment = Regex::new(r" AUTO_INCREMENT=\d+").unwrap();
+    let re_definer = Regex::new(r" ?DEFINER=`[^`]*`@`[^`]*`").unwrap();
+
+    let statement = re_auto_increment.replace_all(statement, "");
+
+    re_definer.replace_all(&statement, "").into_owned()
+}
+
+/// DDL for the append-only history of migration events in MySQL.
+const HISTORY_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS crude_history (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    recorded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    event VARCHAR(32) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    hash VARCHAR(255),
+    success BOOLEAN NOT NULL,
+    error TEXT,
+    applied_by VARCHAR(255),
+    hostname VARCHAR(255),
+    crude_version VARCHAR(64),
+    duration_ms BIGINT
+);
+";
+
+/// DDL for the latest hash of each repeatable migration in MySQL.
+const REPEATABLES_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS crude_repeatables (
+    name VARCHAR(255) PRIMARY KEY,
+    hash VARCHAR(255) NOT NULL,
+    applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    applied_by VARCHAR(255),
+    hostname VARCHAR(255),
+    crude_version VARCHAR(64),
+    duration_ms BIGINT
+);
+";
+
+/// DDL for creating the migrations table in MySQL.
+pub const INIT_UP_SQL: &str = "\
+CREATE TABLE crude_migrations (
+    id INTEGER PRIMARY KEY AUTO_INCREMENT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    name VARCHAR(255) NOT NULL UNIQUE,
+    hash VARCHAR(255) NOT NULL,
+    down_sql LONGTEXT,
+    applied_by VARCHAR(255),
+    hostname VARCHAR(255),
+    crude_version VARCHAR(64),
+    duration_ms BIGINT,
+    seeded BOOLEAN,
+    environment VARCHAR(255)
+);
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_create_statement() {
+        let input = "CREATE TABLE `users` (\n  `id` int NOT NULL AUTO_INCREMENT\n) ENGINE=InnoDB AUTO_INCREMENT=42 DEFAULT CHARSET=utf8mb4";
+        let expected = "CREATE TABLE `users` (\n  `id` int NOT NULL AUTO_INCREMENT\n) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4";
+
+        assert_eq!(clean_create_statement(input), expected);
+    }
+
+    #[test]
+    fn test_clean_create_statement_definer() {
+        let input = "CREATE ALGORITHM=UNDEFINED DEFINER=`app`@`%` SQL SECURITY DEFINER VIEW `v` AS select 1";
+        let expected = "CREATE ALGORITHM=UNDEFINED SQL SECURITY DEFINER VIEW `v` AS select 1";
+
+        assert_eq!(clean_create_statement(input), expected);
+    }
+
+    #[test]
+    fn test_render_up_migration() {
+        let mut migration = Migration::from_db(
+            String::from("20240101000000_users"),
+            String::from("abc"),
+            Some(String::from(r"UPDATE t SET a = 'x\y';")),
+        )
+        .unwrap();
+        migration.up_sql = Some(String::from("CREATE TABLE users (id int);"));
+
+        let execution = Execution::current();
+
+        assert_eq!(
+            render_up(&migration, &render_record(&migration), Dialect::Mysql),
+            format!(
+                "BEGIN;\n\n\
+                CREATE TABLE users (id int);\n\n\
+                INSERT INTO crude_migrations\n\
+                (name, hash, down_sql, applied_by, hostname, crude_version, duration_ms, seeded, environment)\n\
+                VALUES ('20240101000000_users', 'abc', 'UPDATE t SET a = \\'x\\\\y\\';', {}, {}, {}, NULL, FALSE, NULL);\n\n\
+                COMMIT;\n",
+                quote_literal(&execution.applied_by),
+                quote_literal(&execution.hostname),
+                quote_literal(execution.crude_version),
+            )
+        );
+    }
+}
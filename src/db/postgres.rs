@@ -1,20 +1,58 @@
-use std::process::Command;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{read_to_string, write},
+    path::Path,
+    process::Command,
+    time::{Duration, Instant},
+};
 
-use postgres::Client;
+use chrono::NaiveDateTime;
+use postgres::{
+    Client, GenericClient, Row, Transaction,
+    error::{ErrorPosition, SqlState},
+};
 use regex::Regex;
 use tracing::warn;
 
-use crate::{db::DatabaseAdapter, error::Result, migration::Migration};
+use crate::{
+    db::{
+        DatabaseAdapter, Execution, StatementError, Timeout, add_tracking_columns, elapsed_ms,
+        in_transaction, quote_literal, render_down, render_insert, render_repeat, render_sync,
+        render_up, run_statements, select_migrations, wait_for_lock,
+    },
+    error::Result,
+    migration::{Applied, Migration, Repeatable, history::HistoryEntry},
+    sql::{Dialect, adds_enum_value, autocommit_reason},
+};
+
+/// Key of the session-level advisory lock guarding migrations ("crude").
+const LOCK_KEY: i64 = 0x63_7275_6465;
 
 /// Adapter for Postgres-backed migrations.
 pub struct PostgresAdapter {
     client: Client,
+    native_dump: bool,
+    /// `server_version_num` of the server, once queried.
+    server_version: Option<i32>,
+    /// Whether a transaction spanning several migrations is open, see `begin`.
+    atomic: bool,
+    /// Whether the tracking table has every execution detail column, see `lock`.
+    tracking_ready: bool,
 }
 
 impl PostgresAdapter {
     /// Wrap a `postgres::Client` as a migrator.
-    pub fn new(client: Client) -> Self {
-        PostgresAdapter { client }
+    ///
+    /// With `native_dump`, schema and data dumps are built from the catalog
+    /// over the same connection instead of running `pg_dump`.
+    pub fn new(client: Client, native_dump: bool) -> Self {
+        PostgresAdapter {
+            client,
+            native_dump,
+            server_version: None,
+            atomic: false,
+            tracking_ready: false,
+        }
     }
 }
 
@@ -23,6 +61,53 @@ impl DatabaseAdapter for PostgresAdapter {
         INIT_UP_SQL
     }
 
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
+    fn lock(&mut self, timeout: Duration) -> Result<()> {
+        let client = &mut self.client;
+
+        wait_for_lock(timeout, || {
+            let acquired: bool = client
+                .query_one("SELECT pg_try_advisory_lock($1)", &[&LOCK_KEY])?
+                .get(0);
+
+            if acquired {
+                return Ok(None);
+            }
+
+            // A bigint advisory key is split into classid (high) and objid (low)
+            let holder = client.query_opt(
+                "SELECT format('pid %s, user %s, application %L, client %s, since %s',
+                    a.pid, a.usename, a.application_name,
+                    COALESCE(host(a.client_addr), 'local'), date_trunc('second', a.backend_start))
+                FROM pg_locks l
+                JOIN pg_stat_activity a ON a.pid = l.pid
+                WHERE l.locktype = 'advisory' AND l.granted
+                AND l.classid = $1 AND l.objid = $2 AND l.objsubid = 1",
+                &[&((LOCK_KEY >> 32) as u32), &(LOCK_KEY as u32)],
+            )?;
+
+            Ok(Some(holder.map_or_else(
+                || String::from("another session"),
+                |row| format!("another session ({})", row.get::<_, String>(0)),
+            )))
+        })?;
+
+        // Upgrade an older tracking table once, rather than with every migration
+        self.tracking_ready = upgrade_tracking_table(&mut self.client)?;
+
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> Result<()> {
+        self.client
+            .execute("SELECT pg_advisory_unlock($1)", &[&LOCK_KEY])?;
+
+        Ok(())
+    }
+
     fn load_migrations(&mut self) -> Result<Vec<Migration>> {
         let table_exists = self.client.query(
             "SELECT EXISTS (
@@ -39,8 +124,9 @@ impl DatabaseAdapter for PostgresAdapter {
 
         let mut migrations = Vec::new();
 
+        let existing = tracking_columns(&mut self.client)?;
         let rows = self.client.query(
-            "SELECT name, hash, down_sql FROM crude.migrations ORDER BY id ASC",
+            &select_migrations("crude.migrations", &existing, Dialect::Postgres),
             &[],
         )?;
 
@@ -49,7 +135,20 @@ impl DatabaseAdapter for PostgresAdapter {
             let hash: String = row.get(1);
             let down_sql: Option<String> = row.get(2);
 
-            migrations.push(Migration::from_db(name, hash, down_sql)?);
+            let mut migration = Migration::from_db(name, hash, down_sql)?;
+
+            migration.applied = Some(Applied {
+                applied_at: row.get::<_, Option<NaiveDateTime>>(3),
+                updated_at: row.get::<_, Option<NaiveDateTime>>(4),
+                applied_by: row.get(5),
+                hostname: row.get(6),
+                crude_version: row.get(7),
+                duration_ms: row.get(8),
+                seeded: row.get(9),
+                environment: row.get(10),
+            });
+
+            migrations.push(migration);
         }
 
         Ok(migrations)
@@ -57,40 +156,61 @@ impl DatabaseAdapter for PostgresAdapter {
 
     fn run_up_migration(&mut self, migration: &Migration) -> Result<()> {
         let name = &migration.compound_name;
-        let hash = &migration.hash;
         let up_sql = migration.up_sql.as_ref().unwrap();
-        let down_sql = migration.down_sql.as_deref();
         let seed_sql = migration.seed_sql.as_deref();
 
-        // Detect top-of-file marker to disable transaction
-        let disable_tx = up_sql
-            .trim_start()
-            .to_lowercase()
-            .starts_with("-- no-transaction");
-
-        if disable_tx {
-            // run up outside a transaction
-            self.client.batch_execute(up_sql)?;
-            self.client.execute(
-                "INSERT INTO crude.migrations (name, hash, down_sql) VALUES ($1, $2, $3)",
-                &[name, hash, &down_sql],
+        let autocommit = self.autocommit()?;
+
+        if !in_transaction(
+            name,
+            &migration.source("up.sql"),
+            up_sql,
+            Dialect::Postgres,
+            migration.meta.transaction,
+            &autocommit,
+        )? || self.atomic
+        {
+            // run up outside a transaction, or in the one of an atomic plan
+            let start = Instant::now();
+            run_statements(
+                name,
+                &migration.source("up.sql"),
+                up_sql,
+                Dialect::Postgres,
+                |sql| execute(&mut self.client, sql),
+            )?;
+            record_migration(
+                &mut self.client,
+                migration,
+                elapsed_ms(start),
+                !self.tracking_ready,
             )?;
         } else {
             // run up + record inside a transaction
             let mut tx = self.client.transaction()?;
-            tx.batch_execute(up_sql)?;
-            tx.execute(
-                "INSERT INTO crude.migrations (name, hash, down_sql) VALUES ($1, $2, $3)",
-                &[name, hash, &down_sql],
+            let start = Instant::now();
+            run_statements(
+                name,
+                &migration.source("up.sql"),
+                up_sql,
+                Dialect::Postgres,
+                |sql| execute(&mut tx, sql),
             )?;
+            record_migration(&mut tx, migration, elapsed_ms(start), !self.tracking_ready)?;
             tx.commit()?;
         }
 
+        self.tracking_ready = true;
+
         // always run seed in its own transaction if provided
         if let Some(seed) = seed_sql {
-            let mut tx = self.client.transaction()?;
-            tx.batch_execute(seed)?;
-            tx.commit()?;
+            if self.atomic {
+                run_seed(&mut self.client, migration, seed)?;
+            } else {
+                let mut tx = self.client.transaction()?;
+                run_seed(&mut tx, migration, seed)?;
+                tx.commit()?;
+            }
         }
 
         Ok(())
@@ -100,19 +220,35 @@ impl DatabaseAdapter for PostgresAdapter {
         let name = &migration.compound_name;
         let down_sql = migration.down_sql.as_ref().unwrap();
 
-        // Detect no-transaction marker
-        let disable_tx = down_sql
-            .trim_start()
-            .to_lowercase()
-            .starts_with("-- no-transaction");
+        let autocommit = self.autocommit()?;
 
-        if disable_tx {
-            self.client.batch_execute(down_sql)?;
+        if !in_transaction(
+            name,
+            &migration.source("down.sql"),
+            down_sql,
+            Dialect::Postgres,
+            migration.meta.transaction,
+            &autocommit,
+        )? || self.atomic
+        {
+            run_statements(
+                name,
+                &migration.source("down.sql"),
+                down_sql,
+                Dialect::Postgres,
+                |sql| execute(&mut self.client, sql),
+            )?;
             self.client
                 .execute("DELETE FROM crude.migrations WHERE name = $1", &[name])?;
         } else {
             let mut tx = self.client.transaction()?;
-            tx.batch_execute(down_sql)?;
+            run_statements(
+                name,
+                &migration.source("down.sql"),
+                down_sql,
+                Dialect::Postgres,
+                |sql| execute(&mut tx, sql),
+            )?;
             tx.execute("DELETE FROM crude.migrations WHERE name = $1", &[name])?;
             tx.commit()?;
         }
@@ -120,9 +256,57 @@ impl DatabaseAdapter for PostgresAdapter {
         Ok(())
     }
 
+    fn render_up_migration(&self, migration: &Migration) -> String {
+        render_up(migration, &render_record(migration), Dialect::Postgres)
+    }
+
+    fn render_down_migration(&self, migration: &Migration) -> String {
+        let record = format!(
+            "DELETE FROM crude.migrations WHERE name = {};",
+            quote_literal(&migration.compound_name)
+        );
+
+        render_down(migration, &record, Dialect::Postgres)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        self.client.batch_execute("BEGIN")?;
+        self.atomic = true;
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.atomic = false;
+        self.client.batch_execute("COMMIT")?;
+
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.atomic = false;
+        self.client.batch_execute("ROLLBACK")?;
+
+        Ok(())
+    }
+
+    fn set_timeouts(&mut self, statement: Option<Duration>, lock: Option<Duration>) -> Result<()> {
+        let value = |timeout: Option<Duration>| {
+            timeout.map_or_else(|| String::from("DEFAULT"), |t| t.as_millis().to_string())
+        };
+
+        self.client.batch_execute(&format!(
+            "SET statement_timeout = {}; SET lock_timeout = {};",
+            value(statement),
+            value(lock)
+        ))?;
+
+        Ok(())
+    }
+
     fn update_migration_hash(&mut self, name: &str, hash: &str) -> Result<()> {
         self.client.execute(
-            "UPDATE crude.migrations SET hash = $1 WHERE name = $2",
+            "UPDATE crude.migrations SET hash = $1, updated_at = NOW() WHERE name = $2",
             &[&hash, &name],
         )?;
 
@@ -138,15 +322,177 @@ impl DatabaseAdapter for PostgresAdapter {
     }
 
     fn record_baseline(&mut self, name: &str, hash: &str) -> Result<()> {
+        let execution = Execution::current();
+
+        if !self.tracking_ready {
+            self.tracking_ready = upgrade_tracking_table(&mut self.client)?;
+        }
+
         self.client.execute(
-            "INSERT INTO crude.migrations (name, hash) VALUES ($1, $2)",
-            &[&name, &hash],
+            "INSERT INTO crude.migrations (name, hash, applied_by, hostname, crude_version)
+            VALUES ($1, $2, $3, $4, $5)",
+            &[
+                &name,
+                &hash,
+                &execution.applied_by,
+                &execution.hostname,
+                &execution.crude_version,
+            ],
         )?;
 
         Ok(())
     }
 
+    fn render_sync_rollup(&self, rollup: &Migration) -> String {
+        render_sync(
+            "crude.migrations",
+            "DELETE FROM crude.migrations WHERE id > (SELECT MIN(id) FROM crude.migrations);",
+            rollup,
+            quote_literal,
+        )
+    }
+
+    fn record_history(&mut self, entry: &HistoryEntry) -> Result<()> {
+        // Avoid IF NOT EXISTS, its notice would be logged on every event
+        if !self.table_exists("crude.history")? {
+            self.client.batch_execute(HISTORY_TABLE_SQL)?;
+        }
+
+        self.client.execute(
+            "INSERT INTO crude.history
+            (event, name, hash, success, error, applied_by, hostname, crude_version, duration_ms)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[
+                &entry.event.as_str(),
+                &entry.name,
+                &entry.hash,
+                &entry.success,
+                &entry.error,
+                &entry.applied_by,
+                &entry.hostname,
+                &entry.crude_version,
+                &entry.duration_ms,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_history(&mut self) -> Result<Vec<HistoryEntry>> {
+        if !self.table_exists("crude.history")? {
+            return Ok(Vec::new());
+        }
+
+        let rows = self.client.query(
+            "SELECT recorded_at, event, name, hash, success, error,
+                applied_by, hostname, crude_version, duration_ms
+            FROM crude.history ORDER BY id ASC",
+            &[],
+        )?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(HistoryEntry {
+                    recorded_at: row.get(0),
+                    event: row.get::<_, String>(1).parse()?,
+                    name: row.get(2),
+                    hash: row.get(3),
+                    success: row.get(4),
+                    error: row.get(5),
+                    applied_by: row.get(6),
+                    hostname: row.get(7),
+                    crude_version: row.get(8),
+                    duration_ms: row.get(9),
+                })
+            })
+            .collect()
+    }
+
+    fn load_repeatables(&mut self) -> Result<Vec<Repeatable>> {
+        if !self.table_exists("crude.repeatables")? {
+            return Ok(Vec::new());
+        }
+
+        let rows = self.client.query(
+            "SELECT name, hash, applied_at, applied_by, hostname, crude_version, duration_ms
+            FROM crude.repeatables ORDER BY name ASC",
+            &[],
+        )?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                Repeatable::from_db(
+                    row.get(0),
+                    row.get(1),
+                    Applied {
+                        applied_at: row.get(2),
+                        updated_at: None,
+                        applied_by: row.get(3),
+                        hostname: row.get(4),
+                        crude_version: row.get(5),
+                        duration_ms: row.get(6),
+                        seeded: None,
+                        environment: None,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    fn run_repeatable(&mut self, repeatable: &Repeatable) -> Result<()> {
+        let name = repeatable.label();
+        let source = repeatable.source();
+        let sql = repeatable.sql.as_ref().unwrap();
+
+        if !self.table_exists("crude.repeatables")? {
+            self.client.batch_execute(REPEATABLES_TABLE_SQL)?;
+        }
+
+        let autocommit = self.autocommit()?;
+
+        if !in_transaction(&name, &source, sql, Dialect::Postgres, None, &autocommit)?
+            || self.atomic
+        {
+            let start = Instant::now();
+            run_statements(&name, &source, sql, Dialect::Postgres, |sql| {
+                execute(&mut self.client, sql)
+            })?;
+            record_repeatable(&mut self.client, repeatable, elapsed_ms(start))?;
+        } else {
+            let mut tx = self.client.transaction()?;
+            let start = Instant::now();
+            run_statements(&name, &source, sql, Dialect::Postgres, |sql| {
+                execute(&mut tx, sql)
+            })?;
+            record_repeatable(&mut tx, repeatable, elapsed_ms(start))?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    fn render_repeatable(&self, repeatable: &Repeatable) -> String {
+        let record = format!(
+            "{REPEATABLES_TABLE_SQL}\n\
+            INSERT INTO crude.repeatables (name, hash) VALUES ({}, {})\n\
+            ON CONFLICT (name) DO UPDATE SET hash = EXCLUDED.hash, applied_at = NOW();",
+            quote_literal(&repeatable.name),
+            quote_literal(&repeatable.hash),
+        );
+
+        render_repeat(repeatable, &record, Dialect::Postgres)
+    }
+
     fn dump_schema(&mut self, url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
+        if self.native_dump {
+            let mut tx = self.dump_transaction()?;
+            let schema = native_dump_schema(&mut tx, exclude_migrations)?;
+            tx.commit()?;
+
+            return Ok(schema.into_bytes());
+        }
+
         print_pg_dump_version()?;
 
         let mut cmd = Command::new("pg_dump");
@@ -173,6 +519,14 @@ impl DatabaseAdapter for PostgresAdapter {
     }
 
     fn dump_data(&mut self, url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
+        if self.native_dump {
+            let mut tx = self.dump_transaction()?;
+            let data = native_dump_data(&mut tx, exclude_migrations)?;
+            tx.commit()?;
+
+            return Ok(data.into_bytes());
+        }
+
         print_pg_dump_version()?;
 
         let mut cmd = Command::new("pg_dump");
@@ -198,6 +552,797 @@ impl DatabaseAdapter for PostgresAdapter {
 
         Ok(clean_pg_dump_output(output.stdout))
     }
+
+    fn backup(&mut self, url: &str, path: &Path) -> Result<()> {
+        print_pg_dump_version()?;
+
+        // Inserts rather than COPY, so that the dump can be replayed over a connection
+        let output = Command::new("pg_dump")
+            .arg("--inserts")
+            .arg("--no-owner")
+            .arg("--no-privileges")
+            .arg("--exclude-table-data=crude.history")
+            .arg(format!("--dbname={url}"))
+            .output()?;
+
+        if !output.status.success() {
+            return Err(eyre::eyre!(
+                "pg_dump failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        write(path, clean_pg_dump_output(output.stdout))?;
+
+        Ok(())
+    }
+
+    fn restore(&mut self, _url: &str, path: &Path) -> Result<()> {
+        let dump = read_to_string(path)?;
+        let mut tx = self.client.transaction()?;
+
+        let has_history: bool = tx
+            .query_one("SELECT to_regclass('crude.history') IS NOT NULL", &[])?
+            .get(0);
+
+        // Temporary tables survive dropping the schemas, keep the history there meanwhile
+        if has_history {
+            tx.batch_execute(
+                "CREATE TEMP TABLE crude_history_kept AS SELECT * FROM crude.history",
+            )?;
+        }
+
+        let schemas = tx
+            .query(
+                "SELECT quote_ident(nspname) FROM pg_namespace
+                WHERE nspname !~ '^pg_' AND nspname <> 'information_schema'",
+                &[],
+            )?
+            .iter()
+            .map(|row| row.get::<_, String>(0))
+            .collect::<Vec<_>>();
+
+        if !schemas.is_empty() {
+            tx.batch_execute(&format!("DROP SCHEMA {} CASCADE", schemas.join(", ")))?;
+        }
+
+        // Dumps don't create the public schema, which always exists
+        tx.batch_execute("CREATE SCHEMA public")?;
+        tx.batch_execute(&dump)?;
+
+        if has_history {
+            let history_exists: bool = tx
+                .query_one("SELECT to_regclass('crude.history') IS NOT NULL", &[])?
+                .get(0);
+
+            if !history_exists {
+                tx.batch_execute(HISTORY_TABLE_SQL)?;
+            }
+
+            tx.batch_execute(
+                "INSERT INTO crude.history (id, recorded_at, event, name, hash, success, error,
+                    applied_by, hostname, crude_version, duration_ms)
+                OVERRIDING SYSTEM VALUE
+                SELECT id, recorded_at, event, name, hash, success, error,
+                    applied_by, hostname, crude_version, duration_ms
+                FROM pg_temp.crude_history_kept;
+                SELECT setval(pg_get_serial_sequence('crude.history', 'id'), max(id)) FROM crude.history;
+                DROP TABLE pg_temp.crude_history_kept;",
+            )?;
+        }
+
+        tx.commit()?;
+
+        // The dump changes settings of the session, like the search path
+        self.client.batch_execute("RESET ALL")?;
+
+        Ok(())
+    }
+}
+
+impl PostgresAdapter {
+    /// Numeric version of the server, e.g. 160004.
+    fn server_version(&mut self) -> Result<i32> {
+        if let Some(version) = self.server_version {
+            return Ok(version);
+        }
+
+        let version: String = self
+            .client
+            .query_one("SHOW server_version_num", &[])?
+            .get(0);
+        let version = version.parse()?;
+
+        self.server_version = Some(version);
+
+        Ok(version)
+    }
+
+    /// Why statements cannot run in a transaction on this server.
+    fn autocommit(&mut self) -> Result<impl Fn(&str) -> Option<&'static str> + use<>> {
+        // Enum values can be added in a transaction since Postgres 12
+        let old_enums = self.server_version()? < 120000;
+
+        Ok(move |sql: &str| {
+            autocommit_reason(sql, Dialect::Postgres).or_else(|| {
+                (old_enums && adds_enum_value(sql)).then_some("ALTER TYPE ... ADD VALUE")
+            })
+        })
+    }
+
+    /// Whether a table created on demand, like the history, exists.
+    fn table_exists(&mut self, table: &str) -> Result<bool> {
+        Ok(self
+            .client
+            .query_one("SELECT to_regclass($1) IS NOT NULL", &[&table])?
+            .get(0))
+    }
+
+    /// Start a read-only snapshot in which every catalog name is schema qualified.
+    fn dump_transaction(&mut self) -> Result<Transaction<'_>> {
+        let mut tx = self.client.transaction()?;
+
+        tx.batch_execute(
+            "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY;
+            SET LOCAL search_path = pg_catalog;",
+        )?;
+
+        Ok(tx)
+    }
+}
+
+/// Run the seed of a migration and mark it as seeded.
+fn run_seed(client: &mut impl GenericClient, migration: &Migration, seed: &str) -> Result<()> {
+    let name = &migration.compound_name;
+
+    run_statements(
+        name,
+        &migration.source(&migration.seed_file),
+        seed,
+        Dialect::Postgres,
+        |sql| execute(client, sql),
+    )?;
+    client.execute(
+        "UPDATE crude.migrations SET seeded = TRUE WHERE name = $1",
+        &[name],
+    )?;
+
+    Ok(())
+}
+
+/// Execute a single statement, keeping the position of the error reported by the server.
+fn execute(client: &mut impl GenericClient, sql: &str) -> std::result::Result<(), StatementError> {
+    client.batch_execute(sql).map_err(|e| {
+        let position = match e.as_db_error().and_then(|db| db.position()) {
+            Some(ErrorPosition::Original(position)) => Some(*position as usize),
+            _ => None,
+        };
+
+        let timeout = match e.code() {
+            Some(&SqlState::LOCK_NOT_AVAILABLE) => Some(Timeout::Lock),
+            Some(&SqlState::QUERY_CANCELED) => Some(Timeout::Statement),
+            _ => None,
+        };
+
+        StatementError::at(e, position).timed_out(timeout)
+    })
+}
+
+/// Columns of the tracking table, used to detect tables created by older versions.
+fn tracking_columns(client: &mut impl GenericClient) -> Result<Vec<String>> {
+    let rows = client.query(
+        "SELECT column_name::text FROM information_schema.columns
+        WHERE table_schema = 'crude'
+        AND table_name = 'migrations'",
+        &[],
+    )?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Add execution detail columns missing from the tracking table, returning whether it exists.
+fn upgrade_tracking_table(client: &mut impl GenericClient) -> Result<bool> {
+    let existing = tracking_columns(client)?;
+
+    for sql in add_tracking_columns("crude.migrations", &existing, Dialect::Postgres) {
+        client.batch_execute(&sql)?;
+    }
+
+    Ok(!existing.is_empty())
+}
+
+/// Render the record of an applied migration for a standalone SQL script.
+fn render_record(migration: &Migration) -> String {
+    let down_sql = migration
+        .down_sql
+        .as_deref()
+        .map_or_else(|| String::from("NULL"), quote_literal);
+
+    render_insert("crude.migrations", migration, &down_sql, quote_literal)
+}
+
+/// Record an applied migration along with details about this execution.
+fn record_migration(
+    client: &mut impl GenericClient,
+    migration: &Migration,
+    duration_ms: i64,
+    upgrade: bool,
+) -> Result<()> {
+    let execution = Execution::current();
+
+    // The tracking table may have been created by this very migration
+    if upgrade {
+        upgrade_tracking_table(client)?;
+    }
+
+    client.execute(
+        "INSERT INTO crude.migrations
+        (name, hash, down_sql, applied_by, hostname, crude_version, duration_ms, seeded, environment)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, FALSE, $8)",
+        &[
+            &migration.compound_name,
+            &migration.hash,
+            &migration.down_sql,
+            &execution.applied_by,
+            &execution.hostname,
+            &execution.crude_version,
+            &duration_ms,
+            &migration.environment,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Record the latest hash of a repeatable migration along with details about this execution.
+fn record_repeatable(
+    client: &mut impl GenericClient,
+    repeatable: &Repeatable,
+    duration_ms: i64,
+) -> Result<()> {
+    let execution = Execution::current();
+
+    client.execute(
+        "INSERT INTO crude.repeatables
+        (name, hash, applied_by, hostname, crude_version, duration_ms)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (name) DO UPDATE SET
+            hash = EXCLUDED.hash,
+            applied_at = NOW(),
+            applied_by = EXCLUDED.applied_by,
+            hostname = EXCLUDED.hostname,
+            crude_version = EXCLUDED.crude_version,
+            duration_ms = EXCLUDED.duration_ms",
+        &[
+            &repeatable.name,
+            &repeatable.hash,
+            &execution.applied_by,
+            &execution.hostname,
+            &execution.crude_version,
+            &duration_ms,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Condition selecting user namespaces aliased as `n`.
+fn namespace_filter(exclude_migrations: bool) -> String {
+    let mut filter = String::from("n.nspname !~ '^pg_' AND n.nspname <> 'information_schema'");
+
+    if exclude_migrations {
+        filter.push_str(" AND n.nspname <> 'crude'");
+    }
+
+    filter
+}
+
+/// Condition excluding objects (aliased by `alias`) that belong to an extension.
+fn not_extension_member(alias: &str) -> String {
+    format!(
+        "NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = {alias}.oid AND d.deptype = 'e')"
+    )
+}
+
+/// Run a query whose rows consist of a single text column.
+fn query_strings(tx: &mut Transaction, sql: &str) -> Result<Vec<String>> {
+    Ok(tx
+        .query(sql, &[])?
+        .iter()
+        .map(|row| row.get::<_, String>(0))
+        .collect())
+}
+
+/// Object ids in the first column of catalog rows.
+fn oids(rows: &[Row]) -> Vec<u32> {
+    rows.iter().map(|row| row.get(0)).collect()
+}
+
+/// Order objects so that each follows the ones it depends on, otherwise keeping their order.
+///
+/// Dependencies missing from `objects` are ignored. Views and partitions can't depend on each
+/// other in a cycle, but one would be emitted in the given order rather than looping forever.
+fn dependency_order(objects: &[u32], depends_on: &BTreeMap<u32, BTreeSet<u32>>) -> Vec<u32> {
+    let mut remaining = objects.iter().copied().collect::<BTreeSet<_>>();
+    let mut ordered = Vec::new();
+
+    while !remaining.is_empty() {
+        let ready = objects
+            .iter()
+            .copied()
+            .filter(|oid| {
+                remaining.contains(oid)
+                    && depends_on
+                        .get(oid)
+                        .is_none_or(|deps| deps.iter().all(|dep| !remaining.contains(dep)))
+            })
+            .collect::<Vec<_>>();
+
+        let ready = if ready.is_empty() {
+            objects
+                .iter()
+                .copied()
+                .filter(|oid| remaining.contains(oid))
+                .collect()
+        } else {
+            ready
+        };
+
+        for oid in ready {
+            remaining.remove(&oid);
+            ordered.push(oid);
+        }
+    }
+
+    ordered
+}
+
+/// Build the schema DDL from the catalog in a deterministic order.
+fn native_dump_schema(tx: &mut Transaction, exclude_migrations: bool) -> Result<String> {
+    let filter = namespace_filter(exclude_migrations);
+    let mut out = vec![String::from("SET check_function_bodies = false;")];
+
+    // Schemas
+    out.extend(query_strings(
+        tx,
+        &format!(
+            "SELECT format('CREATE SCHEMA %I;', n.nspname)
+            FROM pg_namespace n
+            WHERE {filter} AND n.nspname <> 'public' AND {}
+            ORDER BY n.nspname",
+            not_extension_member("n"),
+        ),
+    )?);
+
+    // Extensions
+    out.extend(query_strings(
+        tx,
+        "SELECT format('CREATE EXTENSION IF NOT EXISTS %I WITH SCHEMA %I;', e.extname, n.nspname)
+        FROM pg_extension e
+        JOIN pg_namespace n ON n.oid = e.extnamespace
+        WHERE e.extname <> 'plpgsql'
+        ORDER BY e.extname",
+    )?);
+
+    // Enum, composite and domain types
+    out.extend(query_strings(
+        tx,
+        &format!(
+            "SELECT format('CREATE TYPE %I.%I AS ENUM (%s);', n.nspname, t.typname, (
+                SELECT string_agg(quote_literal(e.enumlabel), ', ' ORDER BY e.enumsortorder)
+                FROM pg_enum e WHERE e.enumtypid = t.oid
+            ))
+            FROM pg_type t
+            JOIN pg_namespace n ON n.oid = t.typnamespace
+            WHERE t.typtype = 'e' AND {filter} AND {}
+            ORDER BY n.nspname, t.typname",
+            not_extension_member("t"),
+        ),
+    )?);
+
+    out.extend(query_strings(
+        tx,
+        &format!(
+            "SELECT format('CREATE TYPE %I.%I AS (%s);', n.nspname, t.typname, (
+                SELECT string_agg(format('%I %s', a.attname, format_type(a.atttypid, a.atttypmod)), ', ' ORDER BY a.attnum)
+                FROM pg_attribute a
+                WHERE a.attrelid = t.typrelid AND a.attnum > 0 AND NOT a.attisdropped
+            ))
+            FROM pg_type t
+            JOIN pg_class c ON c.oid = t.typrelid
+            JOIN pg_namespace n ON n.oid = t.typnamespace
+            WHERE t.typtype = 'c' AND c.relkind = 'c' AND {filter} AND {}
+            ORDER BY n.nspname, t.typname",
+            not_extension_member("t"),
+        ),
+    )?);
+
+    out.extend(query_strings(
+        tx,
+        &format!(
+            "SELECT format('CREATE DOMAIN %I.%I AS %s%s%s%s;', n.nspname, t.typname,
+                format_type(t.typbasetype, t.typtypmod),
+                CASE WHEN t.typdefault IS NOT NULL THEN ' DEFAULT ' || t.typdefault ELSE '' END,
+                CASE WHEN t.typnotnull THEN ' NOT NULL' ELSE '' END,
+                COALESCE((
+                    SELECT string_agg(format(' CONSTRAINT %I %s', c.conname, pg_get_constraintdef(c.oid, true)), '' ORDER BY c.conname)
+                    FROM pg_constraint c WHERE c.contypid = t.oid
+                ), ''))
+            FROM pg_type t
+            JOIN pg_namespace n ON n.oid = t.typnamespace
+            WHERE t.typtype = 'd' AND {filter} AND {}
+            ORDER BY n.nspname, t.typname",
+            not_extension_member("t"),
+        ),
+    )?);
+
+    // Functions and procedures
+    out.extend(
+        query_strings(
+            tx,
+            &format!(
+                "SELECT pg_get_functiondef(p.oid)
+                FROM pg_proc p
+                JOIN pg_namespace n ON n.oid = p.pronamespace
+                WHERE p.prokind IN ('f', 'p') AND {filter} AND {}
+                ORDER BY n.nspname, p.proname, pg_get_function_identity_arguments(p.oid)",
+                not_extension_member("p"),
+            ),
+        )?
+        .into_iter()
+        .map(|def| format!("{};", def.trim_end())),
+    );
+
+    // Sequences not backing identity columns
+    out.extend(query_strings(
+        tx,
+        &format!(
+            "SELECT format('CREATE SEQUENCE %I.%I AS %s START WITH %s INCREMENT BY %s MINVALUE %s MAXVALUE %s CACHE %s%s;',
+                n.nspname, c.relname, format_type(s.seqtypid, NULL), s.seqstart, s.seqincrement,
+                s.seqmin, s.seqmax, s.seqcache, CASE WHEN s.seqcycle THEN ' CYCLE' ELSE '' END)
+            FROM pg_sequence s
+            JOIN pg_class c ON c.oid = s.seqrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE {filter}
+            AND NOT EXISTS (SELECT 1 FROM pg_depend d WHERE d.objid = c.oid AND d.deptype IN ('e', 'i'))
+            ORDER BY n.nspname, c.relname",
+        ),
+    )?);
+
+    // Tables, partitions after their parents
+    let tables = tx.query(
+        &format!(
+            "SELECT c.oid, format('%I.%I', n.nspname, c.relname),
+                CASE WHEN c.relispartition THEN (
+                    SELECT format('%I.%I', pn.nspname, pc.relname)
+                    FROM pg_inherits i
+                    JOIN pg_class pc ON pc.oid = i.inhparent
+                    JOIN pg_namespace pn ON pn.oid = pc.relnamespace
+                    WHERE i.inhrelid = c.oid
+                ) END,
+                CASE WHEN c.relispartition THEN pg_get_expr(c.relpartbound, c.oid) END,
+                CASE WHEN c.relkind = 'p' THEN pg_get_partkeydef(c.oid) END,
+                CASE WHEN c.relispartition THEN (
+                    SELECT i.inhparent FROM pg_inherits i WHERE i.inhrelid = c.oid
+                ) END
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind IN ('r', 'p') AND {filter} AND {}
+            ORDER BY c.relispartition, n.nspname, c.relname",
+            not_extension_member("c"),
+        ),
+        &[],
+    )?;
+
+    // A partition may itself be partitioned, its own partitions must follow it
+    let parents = tables
+        .iter()
+        .filter_map(|row| {
+            let parent = row.get::<_, Option<u32>>(5)?;
+
+            Some((row.get::<_, u32>(0), BTreeSet::from([parent])))
+        })
+        .collect();
+
+    for oid in dependency_order(&oids(&tables), &parents) {
+        let table = tables
+            .iter()
+            .find(|row| row.get::<_, u32>(0) == oid)
+            .unwrap();
+        let oid: u32 = table.get(0);
+        let name: String = table.get(1);
+        let parent: Option<String> = table.get(2);
+        let bound: Option<String> = table.get(3);
+        let partition_key: Option<String> = table.get(4);
+
+        let mut statement = if let Some(parent) = parent {
+            format!(
+                "CREATE TABLE {name} PARTITION OF {parent} {}",
+                bound.unwrap_or_default()
+            )
+        } else {
+            let columns = tx
+                .query(
+                    "SELECT format('%I %s', a.attname, format_type(a.atttypid, a.atttypmod))
+                        || CASE WHEN a.attcollation <> 0 AND a.attcollation <> t.typcollation THEN (
+                            SELECT format(' COLLATE %I.%I', cn.nspname, co.collname)
+                            FROM pg_collation co
+                            JOIN pg_namespace cn ON cn.oid = co.collnamespace
+                            WHERE co.oid = a.attcollation
+                        ) ELSE '' END
+                        || CASE
+                            WHEN a.attgenerated = 's' THEN format(' GENERATED ALWAYS AS (%s) STORED', pg_get_expr(ad.adbin, ad.adrelid))
+                            WHEN ad.adbin IS NOT NULL THEN ' DEFAULT ' || pg_get_expr(ad.adbin, ad.adrelid)
+                            ELSE ''
+                        END
+                        || CASE a.attidentity
+                            WHEN 'a' THEN ' GENERATED ALWAYS AS IDENTITY'
+                            WHEN 'd' THEN ' GENERATED BY DEFAULT AS IDENTITY'
+                            ELSE ''
+                        END
+                        || CASE WHEN a.attnotnull THEN ' NOT NULL' ELSE '' END
+                    FROM pg_attribute a
+                    JOIN pg_type t ON t.oid = a.atttypid
+                    LEFT JOIN pg_attrdef ad ON ad.adrelid = a.attrelid AND ad.adnum = a.attnum
+                    WHERE a.attrelid = $1 AND a.attnum > 0 AND NOT a.attisdropped
+                    ORDER BY a.attnum",
+                    &[&oid],
+                )?
+                .iter()
+                .map(|row| format!("    {}", row.get::<_, String>(0)))
+                .collect::<Vec<_>>();
+
+            format!("CREATE TABLE {name} (\n{}\n)", columns.join(",\n"))
+        };
+
+        if let Some(key) = partition_key {
+            statement.push_str(&format!(" PARTITION BY {key}"));
+        }
+
+        out.push(format!("{statement};"));
+    }
+
+    // Sequence ownership
+    out.extend(query_strings(
+        tx,
+        &format!(
+            "SELECT format('ALTER SEQUENCE %I.%I OWNED BY %I.%I.%I;', n.nspname, s.relname, tn.nspname, t.relname, a.attname)
+            FROM pg_depend d
+            JOIN pg_class s ON s.oid = d.objid AND s.relkind = 'S'
+            JOIN pg_namespace n ON n.oid = s.relnamespace
+            JOIN pg_class t ON t.oid = d.refobjid
+            JOIN pg_namespace tn ON tn.oid = t.relnamespace
+            JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = d.refobjsubid
+            WHERE d.classid = 'pg_class'::regclass AND d.refclassid = 'pg_class'::regclass
+            AND d.deptype = 'a' AND {filter}
+            ORDER BY n.nspname, s.relname",
+        ),
+    )?);
+
+    // Constraints except foreign keys, which need every referenced key first
+    let constraints = |foreign: bool| {
+        format!(
+            "SELECT format('ALTER TABLE %I.%I ADD CONSTRAINT %I %s;', n.nspname, c.relname, co.conname, pg_get_constraintdef(co.oid, true))
+            FROM pg_constraint co
+            JOIN pg_class c ON c.oid = co.conrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE co.contype IN ({}) AND co.conparentid = 0 AND {filter}
+            ORDER BY n.nspname, c.relname, co.conname",
+            if foreign { "'f'" } else { "'p', 'u', 'c', 'x'" },
+        )
+    };
+
+    out.extend(query_strings(tx, &constraints(false))?);
+
+    // Indexes not backing a constraint
+    out.extend(
+        query_strings(
+            tx,
+            &format!(
+                "SELECT pg_get_indexdef(i.indexrelid)
+                FROM pg_index i
+                JOIN pg_class c ON c.oid = i.indexrelid
+                JOIN pg_class t ON t.oid = i.indrelid
+                JOIN pg_namespace n ON n.oid = c.relnamespace
+                WHERE NOT c.relispartition AND {filter}
+                AND NOT EXISTS (
+                    SELECT 1 FROM pg_constraint co
+                    WHERE co.conindid = i.indexrelid AND co.contype IN ('p', 'u', 'x')
+                )
+                ORDER BY n.nspname, t.relname, c.relname",
+            ),
+        )?
+        .into_iter()
+        .map(|def| format!("{def};")),
+    );
+
+    // Views, ordered so that every view follows the views it selects from
+    let views = tx.query(
+        &format!(
+            "SELECT c.oid, format('%I.%I', n.nspname, c.relname), c.relkind = 'm', pg_get_viewdef(c.oid, true)
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind IN ('v', 'm') AND {filter} AND {}
+            ORDER BY n.nspname, c.relname",
+            not_extension_member("c"),
+        ),
+        &[],
+    )?;
+
+    let dependencies = tx.query(
+        "SELECT DISTINCT r.ev_class, d.refobjid
+        FROM pg_rewrite r
+        JOIN pg_depend d ON d.objid = r.oid
+        WHERE d.classid = 'pg_rewrite'::regclass
+        AND d.refclassid = 'pg_class'::regclass
+        AND d.refobjid <> r.ev_class",
+        &[],
+    )?;
+
+    let mut depends_on = BTreeMap::<u32, BTreeSet<u32>>::new();
+
+    for row in dependencies {
+        depends_on.entry(row.get(0)).or_default().insert(row.get(1));
+    }
+
+    for oid in dependency_order(&oids(&views), &depends_on) {
+        let row = views
+            .iter()
+            .find(|row| row.get::<_, u32>(0) == oid)
+            .unwrap();
+        let name: String = row.get(1);
+        let materialized: bool = row.get(2);
+        let definition: String = row.get(3);
+        let definition = definition.trim_end().trim_end_matches(';');
+
+        out.push(if materialized {
+            format!("CREATE MATERIALIZED VIEW {name} AS\n{definition}\nWITH NO DATA;")
+        } else {
+            format!("CREATE VIEW {name} AS\n{definition};")
+        });
+    }
+
+    // Foreign keys
+    out.extend(query_strings(tx, &constraints(true))?);
+
+    // Triggers
+    out.extend(
+        query_strings(
+            tx,
+            &format!(
+                "SELECT pg_get_triggerdef(tg.oid, true)
+                FROM pg_trigger tg
+                JOIN pg_class c ON c.oid = tg.tgrelid
+                JOIN pg_namespace n ON n.oid = c.relnamespace
+                WHERE NOT tg.tgisinternal AND tg.tgparentid = 0 AND {filter}
+                ORDER BY n.nspname, c.relname, tg.tgname",
+            ),
+        )?
+        .into_iter()
+        .map(|def| format!("{def};")),
+    );
+
+    // Comments on relations and their columns
+    out.extend(query_strings(
+        tx,
+        &format!(
+            "SELECT CASE WHEN d.objsubid = 0 THEN
+                    format('COMMENT ON %s %I.%I IS %L;',
+                        CASE c.relkind WHEN 'v' THEN 'VIEW' WHEN 'm' THEN 'MATERIALIZED VIEW' ELSE 'TABLE' END,
+                        n.nspname, c.relname, d.description)
+                ELSE
+                    format('COMMENT ON COLUMN %I.%I.%I IS %L;', n.nspname, c.relname, a.attname, d.description)
+                END
+            FROM pg_description d
+            JOIN pg_class c ON c.oid = d.objoid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            LEFT JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum = d.objsubid
+            WHERE d.classoid = 'pg_class'::regclass AND c.relkind IN ('r', 'p', 'v', 'm') AND {filter}
+            ORDER BY n.nspname, c.relname, d.objsubid",
+        ),
+    )?);
+
+    Ok(out.join("\n\n") + "\n")
+}
+
+/// Build INSERT statements for every table followed by sequence positions.
+fn native_dump_data(tx: &mut Transaction, exclude_migrations: bool) -> Result<String> {
+    let filter = namespace_filter(exclude_migrations);
+    let mut out = Vec::new();
+
+    let tables = tx.query(
+        &format!(
+            "SELECT c.oid, format('%I.%I', n.nspname, c.relname)
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind = 'r' AND {filter} AND {}
+            ORDER BY n.nspname, c.relname",
+            not_extension_member("c"),
+        ),
+        &[],
+    )?;
+
+    for table in tables {
+        let oid: u32 = table.get(0);
+        let name: String = table.get(1);
+
+        let columns = tx.query(
+            "SELECT quote_ident(a.attname), a.attidentity = 'a'
+            FROM pg_attribute a
+            WHERE a.attrelid = $1 AND a.attnum > 0 AND NOT a.attisdropped AND a.attgenerated = ''
+            ORDER BY a.attnum",
+            &[&oid],
+        )?;
+
+        if columns.is_empty() {
+            continue;
+        }
+
+        let names = columns
+            .iter()
+            .map(|row| row.get::<_, String>(0))
+            .collect::<Vec<_>>();
+
+        let overriding = if columns.iter().any(|row| row.get::<_, bool>(1)) {
+            " OVERRIDING SYSTEM VALUE"
+        } else {
+            ""
+        };
+
+        let select = names
+            .iter()
+            .map(|column| format!("quote_nullable(t.{column})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut rows = tx
+            .query(&format!("SELECT {select} FROM {name} t"), &[])?
+            .iter()
+            .map(|row| {
+                (0..names.len())
+                    .map(|i| row.get::<_, String>(i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect::<Vec<_>>();
+
+        rows.sort();
+
+        for row in rows {
+            out.push(format!(
+                "INSERT INTO {name} ({}){overriding} VALUES ({row});",
+                names.join(", ")
+            ));
+        }
+    }
+
+    let sequences = query_strings(
+        tx,
+        &format!(
+            "SELECT format('%I.%I', n.nspname, c.relname)
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE c.relkind = 'S' AND {filter} AND {}
+            ORDER BY n.nspname, c.relname",
+            not_extension_member("c"),
+        ),
+    )?;
+
+    for sequence in sequences {
+        let row = tx.query_one(
+            &format!("SELECT quote_literal($1), last_value::text, is_called::text FROM {sequence}"),
+            &[&sequence],
+        )?;
+
+        out.push(format!(
+            "SELECT pg_catalog.setval({}, {}, {});",
+            row.get::<_, String>(0),
+            row.get::<_, String>(1),
+            row.get::<_, String>(2),
+        ));
+    }
+
+    Ok(out.join("\n") + "\n")
 }
 
 /// Clean up pg_dump output to be consistent across environments.
@@ -228,6 +1373,36 @@ fn print_pg_dump_version() -> Result<()> {
     Ok(())
 }
 
+/// DDL for the append-only history of migration events in Postgres.
+const HISTORY_TABLE_SQL: &str = "\
+CREATE TABLE crude.history (
+    id BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    recorded_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    event VARCHAR(32) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    hash VARCHAR(255),
+    success BOOLEAN NOT NULL,
+    error TEXT,
+    applied_by VARCHAR(255),
+    hostname VARCHAR(255),
+    crude_version VARCHAR(64),
+    duration_ms BIGINT
+);
+";
+
+/// DDL for the latest hash of each repeatable migration in Postgres.
+const REPEATABLES_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS crude.repeatables (
+    name VARCHAR(255) PRIMARY KEY,
+    hash VARCHAR(255) NOT NULL,
+    applied_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    applied_by VARCHAR(255),
+    hostname VARCHAR(255),
+    crude_version VARCHAR(64),
+    duration_ms BIGINT
+);
+";
+
 /// DDL for creating the migrations table in Postgres.
 pub const INIT_UP_SQL: &str = "\
 CREATE SCHEMA crude;
@@ -239,12 +1414,20 @@ CREATE TABLE crude.migrations (
     name VARCHAR(255) NOT NULL,
     hash VARCHAR(255) NOT NULL,
     down_sql TEXT,
+    applied_by VARCHAR(255),
+    hostname VARCHAR(255),
+    crude_version VARCHAR(64),
+    duration_ms BIGINT,
+    seeded BOOLEAN,
+    environment VARCHAR(255),
     UNIQUE (name)
 );
 ";
 
 #[cfg(test)]
 mod tests {
+    use postgres::NoTls;
+
     use super::*;
 
     #[test]
@@ -287,4 +1470,172 @@ More SQL;
             String::from_utf8_lossy(expected)
         );
     }
+
+    #[test]
+    fn test_render_up_migration() {
+        let mut migration = Migration::from_db(
+            String::from("20240101000000_users"),
+            String::from("abc"),
+            Some(String::from("DROP TABLE users;")),
+        )
+        .unwrap();
+        migration.up_sql = Some(String::from("CREATE TABLE users (id int);"));
+        migration.seed_sql = Some(String::from("INSERT INTO users VALUES (1);"));
+        migration.environment = Some(String::from("dev"));
+
+        let execution = Execution::current();
+
+        // The seed shares the transaction of the migration and its record
+        assert_eq!(
+            render_up(&migration, &render_record(&migration), Dialect::Postgres),
+            format!(
+                "BEGIN;\n\n\
+                CREATE TABLE users (id int);\n\n\
+                INSERT INTO users VALUES (1);\n\n\
+                INSERT INTO crude.migrations\n\
+                (name, hash, down_sql, applied_by, hostname, crude_version, duration_ms, seeded, environment)\n\
+                VALUES ('20240101000000_users', 'abc', 'DROP TABLE users;', {}, {}, {}, NULL, TRUE, 'dev');\n\n\
+                COMMIT;\n",
+                quote_literal(&execution.applied_by),
+                quote_literal(&execution.hostname),
+                quote_literal(execution.crude_version),
+            )
+        );
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let depends_on = BTreeMap::from([
+            (1, BTreeSet::from([3])),
+            (3, BTreeSet::from([4])),
+            (2, BTreeSet::from([99])),
+        ]);
+
+        assert_eq!(
+            dependency_order(&[1, 2, 3, 4], &depends_on),
+            vec![2, 4, 3, 1]
+        );
+
+        // A cycle is emitted in the given order
+        let cycle = BTreeMap::from([(1, BTreeSet::from([2])), (2, BTreeSet::from([1]))]);
+
+        assert_eq!(dependency_order(&[2, 1], &cycle), vec![2, 1]);
+    }
+
+    /// Database created for a test on the Postgres server of `DATABASE_URL`, such as the
+    /// one of docker-compose.yml, and dropped after it.
+    struct ScratchDb {
+        admin: Client,
+        config: postgres::Config,
+        name: String,
+    }
+
+    impl ScratchDb {
+        /// Create the database, or `None` to skip the test when `DATABASE_URL` is not Postgres.
+        fn create(suffix: &str) -> Option<Self> {
+            let url = std::env::var("DATABASE_URL")
+                .ok()
+                .filter(|url| url.starts_with("postgres"))?;
+            let config = url.parse::<postgres::Config>().unwrap();
+            let name = format!("crude_test_{}_{suffix}", std::process::id());
+
+            let mut admin = config.connect(NoTls).unwrap();
+            admin
+                .batch_execute(&format!("DROP DATABASE IF EXISTS {name}"))
+                .unwrap();
+            admin
+                .batch_execute(&format!("CREATE DATABASE {name}"))
+                .unwrap();
+
+            Some(ScratchDb {
+                admin,
+                config,
+                name,
+            })
+        }
+
+        fn adapter(&self) -> PostgresAdapter {
+            let client = self
+                .config
+                .clone()
+                .dbname(&self.name)
+                .connect(NoTls)
+                .unwrap();
+
+            PostgresAdapter::new(client, true)
+        }
+    }
+
+    impl Drop for ScratchDb {
+        fn drop(&mut self) {
+            let _ = self.admin.batch_execute(&format!(
+                "DROP DATABASE IF EXISTS {} WITH (FORCE)",
+                self.name
+            ));
+        }
+    }
+
+    const FIXTURE_SQL: &str = r#"
+CREATE SCHEMA "Sales Dept";
+CREATE TYPE "Sales Dept".status AS ENUM ('new', 'it''s done');
+CREATE DOMAIN positive AS integer CHECK (VALUE > 0);
+CREATE TABLE "Sales Dept"."Orders" (
+    id bigint GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "Note" text DEFAULT 'n/a',
+    status "Sales Dept".status NOT NULL DEFAULT 'new',
+    qty positive,
+    total numeric(10, 2) GENERATED ALWAYS AS (qty * 1.5) STORED
+);
+CREATE TABLE lines (
+    id serial PRIMARY KEY,
+    order_id bigint REFERENCES "Sales Dept"."Orders" (id)
+);
+COMMENT ON TABLE lines IS 'Order ''lines''';
+CREATE TABLE events (at date NOT NULL, region text NOT NULL) PARTITION BY RANGE (at);
+CREATE TABLE z_events_2024 PARTITION OF events
+    FOR VALUES FROM ('2024-01-01') TO ('2025-01-01') PARTITION BY LIST (region);
+CREATE TABLE a_events_2024_eu PARTITION OF z_events_2024 FOR VALUES IN ('eu');
+CREATE VIEW z_orders AS SELECT id, "Note" FROM "Sales Dept"."Orders";
+CREATE VIEW a_first_orders AS SELECT * FROM z_orders WHERE id < 10;
+INSERT INTO "Sales Dept"."Orders" ("Note", status, qty)
+    VALUES ('it''s', 'it''s done', 2), (NULL, 'new', 1);
+INSERT INTO lines (order_id) VALUES (1);
+INSERT INTO events VALUES ('2024-03-01', 'eu');
+"#;
+
+    #[test]
+    fn test_native_dump_round_trip() {
+        let (Some(source), Some(target)) =
+            (ScratchDb::create("source"), ScratchDb::create("target"))
+        else {
+            return;
+        };
+
+        let mut db = source.adapter();
+        db.client.batch_execute(FIXTURE_SQL).unwrap();
+
+        let schema = String::from_utf8(db.dump_schema("", true).unwrap()).unwrap();
+        let data = String::from_utf8(db.dump_data("", true).unwrap()).unwrap();
+
+        let position = |needle: &str| schema.find(needle).unwrap();
+
+        assert!(position("z_events_2024 PARTITION OF") < position("a_events_2024_eu PARTITION OF"));
+        assert!(position("VIEW public.z_orders") < position("VIEW public.a_first_orders"));
+        assert!(schema.contains(r#"CREATE TABLE "Sales Dept"."Orders" ("#));
+        assert!(data.contains("'it''s'"));
+
+        // Replaying the dumps rebuilds the same database
+        let mut copy = target.adapter();
+        copy.client.batch_execute(&schema).unwrap();
+        copy.client.batch_execute(&data).unwrap();
+
+        assert_eq!(
+            String::from_utf8(copy.dump_schema("", true).unwrap()).unwrap(),
+            schema
+        );
+        assert_eq!(
+            String::from_utf8(copy.dump_data("", true).unwrap()).unwrap(),
+            data
+        );
+    }
 }
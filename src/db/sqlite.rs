@@ -1,18 +1,229 @@
-use std::process::Command;
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use rusqlite::{Connection, params};
+use chrono::NaiveDateTime;
+use eyre::eyre;
+use rusqlite::{
+    Connection, DatabaseName, OpenFlags, OptionalExtension, TransactionBehavior, backup::Progress,
+    params, types::Type,
+};
+use tracing::warn;
 
-use crate::{db::DatabaseAdapter, error::Result, migration::Migration};
+use crate::{
+    db::{
+        CRUDE_TABLES, DatabaseAdapter, Execution, add_tracking_columns, elapsed_ms, in_transaction,
+        lock_holder, quote_literal, render_down, render_insert, render_repeat, render_sync,
+        render_up, run_statements, select_migrations, wait_for_lock,
+    },
+    error::Result,
+    migration::{Applied, Migration, Repeatable, history::HistoryEntry},
+    sql::{Dialect, autocommit_reason},
+};
+
+/// Open mode of a SQLite database, from the `mode` URL parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqliteMode {
+    ReadOnly,
+    ReadWrite,
+    /// Read-write, creating the database if missing.
+    Create,
+}
+
+/// Connection settings parsed from a `sqlite:` URL.
+///
+/// Accepts `sqlite:///abs/path`, `sqlite://rel/path`, `sqlite:path` and
+/// `sqlite::memory:`, followed by optional `?key=value&...` parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqliteUrl {
+    /// Database file, or `None` for an in-memory database.
+    pub path: Option<PathBuf>,
+    pub mode: SqliteMode,
+    pub journal_mode: Option<String>,
+    pub busy_timeout: Option<Duration>,
+    pub foreign_keys: Option<bool>,
+}
+
+impl SqliteUrl {
+    /// Parse a `sqlite:` URL.
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("sqlite:")
+            .ok_or_else(|| eyre!("invalid sqlite URL: {url}"))?;
+
+        let (location, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let location = location.strip_prefix("//").unwrap_or(location);
+
+        let mut parsed = SqliteUrl {
+            path: match location {
+                ":memory:" => None,
+                "" => return Err(eyre!("missing database path in sqlite URL: {url}")),
+                path => Some(PathBuf::from(path)),
+            },
+            mode: SqliteMode::Create,
+            journal_mode: None,
+            busy_timeout: None,
+            foreign_keys: None,
+        };
+
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| eyre!("invalid sqlite URL parameter: {param}"))?;
+
+            match key {
+                "mode" => {
+                    parsed.mode = match value {
+                        "ro" => SqliteMode::ReadOnly,
+                        "rw" => SqliteMode::ReadWrite,
+                        "rwc" => SqliteMode::Create,
+                        "memory" => {
+                            parsed.path = None;
+                            SqliteMode::Create
+                        }
+                        _ => return Err(eyre!("invalid sqlite mode: {value}")),
+                    }
+                }
+                "journal_mode" => {
+                    let value = value.to_lowercase();
+
+                    if !["delete", "truncate", "persist", "memory", "wal", "off"]
+                        .contains(&value.as_str())
+                    {
+                        return Err(eyre!("invalid sqlite journal_mode: {value}"));
+                    }
+
+                    parsed.journal_mode = Some(value);
+                }
+                "busy_timeout" => {
+                    let millis = value
+                        .parse()
+                        .map_err(|_| eyre!("invalid sqlite busy_timeout: {value}"))?;
+
+                    parsed.busy_timeout = Some(Duration::from_millis(millis));
+                }
+                "foreign_keys" => {
+                    parsed.foreign_keys = Some(match value.to_lowercase().as_str() {
+                        "on" | "true" | "1" => true,
+                        "off" | "false" | "0" => false,
+                        _ => return Err(eyre!("invalid sqlite foreign_keys: {value}")),
+                    });
+                }
+                _ => return Err(eyre!("unsupported sqlite URL parameter: {key}")),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Open the database and apply the pragmas from the URL.
+    pub fn open(&self) -> Result<Connection> {
+        let flags = match self.mode {
+            SqliteMode::ReadOnly => {
+                (OpenFlags::default()
+                    - OpenFlags::SQLITE_OPEN_READ_WRITE
+                    - OpenFlags::SQLITE_OPEN_CREATE)
+                    | OpenFlags::SQLITE_OPEN_READ_ONLY
+            }
+            SqliteMode::ReadWrite => OpenFlags::default() - OpenFlags::SQLITE_OPEN_CREATE,
+            SqliteMode::Create => OpenFlags::default(),
+        };
+
+        let conn = match &self.path {
+            Some(path) => Connection::open_with_flags(path, flags)?,
+            None => Connection::open_in_memory_with_flags(flags)?,
+        };
+
+        if let Some(timeout) = self.busy_timeout {
+            conn.busy_timeout(timeout)?;
+        }
+
+        if let Some(journal_mode) = &self.journal_mode {
+            conn.pragma_update_and_check(None, "journal_mode", journal_mode, |_| Ok(()))?;
+        }
+
+        if let Some(foreign_keys) = self.foreign_keys {
+            conn.pragma_update(None, "foreign_keys", foreign_keys)?;
+        }
+
+        Ok(conn)
+    }
+}
 
 /// Adapter for SQLite-backed migrations.
 pub struct SqliteAdapter {
     conn: Connection,
+    locked: bool,
+    /// Busy timeout (ms) of the connection, saved while a migration overrides it.
+    busy_timeout: Option<i64>,
+    /// Whether the tracking table has every execution detail column, see `lock`.
+    tracking_ready: bool,
 }
 
 impl SqliteAdapter {
     /// Wrap a `rusqlite::Connection` as a migrator.
     pub fn new(conn: Connection) -> Self {
-        SqliteAdapter { conn }
+        SqliteAdapter {
+            conn,
+            locked: false,
+            busy_timeout: None,
+            tracking_ready: false,
+        }
+    }
+}
+
+impl Drop for SqliteAdapter {
+    fn drop(&mut self) {
+        // The lock row outlives the connection, release it even on errors
+        if self.locked {
+            let _ = self.unlock();
+        }
+    }
+}
+
+/// Savepoint of a migration, nesting inside the transaction of `begin` if any.
+///
+/// Unlike a rusqlite savepoint, it is also released when dropped uncommitted. Only
+/// rolling back to it would leave open the transaction it began, along with everything
+/// run on the connection after the failure.
+struct Savepoint<'a> {
+    conn: &'a Connection,
+    committed: bool,
+}
+
+impl<'a> Savepoint<'a> {
+    fn new(conn: &'a Connection) -> Result<Self> {
+        conn.execute_batch("SAVEPOINT crude")?;
+
+        Ok(Savepoint {
+            conn,
+            committed: false,
+        })
+    }
+
+    fn commit(mut self) -> Result<()> {
+        self.conn.execute_batch("RELEASE crude")?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Deref for Savepoint<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.conn.execute_batch("ROLLBACK TO crude; RELEASE crude");
+        }
     }
 }
 
@@ -21,6 +232,99 @@ impl DatabaseAdapter for SqliteAdapter {
         INIT_UP_SQL
     }
 
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn lock(&mut self, timeout: Duration) -> Result<()> {
+        // Nothing can be migrated through a read-only connection, nor locked
+        if self.conn.is_readonly(DatabaseName::Main)? {
+            return Ok(());
+        }
+
+        let holder = lock_holder();
+        let pid = i64::from(std::process::id());
+        let hostname = Execution::current().hostname;
+        let conn = &mut self.conn;
+
+        conn.execute_batch(LOCK_TABLE_SQL)?;
+
+        wait_for_lock(timeout, || {
+            // BEGIN IMMEDIATE serializes concurrent attempts to take the lock row
+            let tx = match conn.transaction_with_behavior(TransactionBehavior::Immediate) {
+                Ok(tx) => tx,
+                Err(rusqlite::Error::SqliteFailure(e, _))
+                    if e.code == rusqlite::ErrorCode::DatabaseBusy =>
+                {
+                    return Ok(Some(String::from("another connection")));
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let current = tx
+                .query_row(
+                    "SELECT holder || ' since ' || acquired_at, pid, hostname
+                    FROM crude_lock WHERE id = 1",
+                    params![],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, i64>(1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    },
+                )
+                .optional()?;
+
+            // The row outlives a killed process, which can't have released it
+            let current = match current {
+                Some((current, their_pid, their_host))
+                    if their_host == hostname && process_gone(their_pid) =>
+                {
+                    warn!("taking over the migration lock held by {current}, its process is gone");
+
+                    tx.execute("DELETE FROM crude_lock WHERE id = 1", params![])?;
+
+                    None
+                }
+                current => current.map(|(current, ..)| current),
+            };
+
+            if current.is_none() {
+                tx.execute(
+                    "INSERT INTO crude_lock (id, holder, pid, hostname) VALUES (1, ?1, ?2, ?3)",
+                    params![holder, pid, hostname],
+                )?;
+            }
+
+            tx.commit()?;
+
+            Ok(current.map(|current| {
+                format!("{current}, delete the row in crude_lock if that process is gone")
+            }))
+        })?;
+
+        self.locked = true;
+
+        // Upgrade an older tracking table once, rather than with every migration
+        self.tracking_ready = upgrade_tracking_table(&self.conn)?;
+
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> Result<()> {
+        if !self.locked {
+            return Ok(());
+        }
+
+        self.conn
+            .execute("DELETE FROM crude_lock WHERE id = 1", params![])?;
+
+        self.locked = false;
+
+        Ok(())
+    }
+
     fn load_migrations(&mut self) -> Result<Vec<Migration>> {
         // Check if the crude_migrations table exists
         let table_exists = self
@@ -37,23 +341,38 @@ impl DatabaseAdapter for SqliteAdapter {
             return Ok(Vec::new());
         }
 
-        let mut stmt = self
-            .conn
-            .prepare("SELECT name, hash, down_sql FROM crude_migrations ORDER BY id ASC")?;
+        let existing = tracking_columns(&self.conn)?;
+        let mut stmt = self.conn.prepare(&select_migrations(
+            "crude_migrations",
+            &existing,
+            Dialect::Sqlite,
+        ))?;
 
         let rows = stmt.query_map(params![], |row| {
             Ok((
                 row.get::<_, String>(0)?,
                 row.get::<_, String>(1)?,
                 row.get::<_, Option<String>>(2)?,
+                Applied {
+                    applied_at: row.get::<_, Option<NaiveDateTime>>(3)?,
+                    updated_at: row.get::<_, Option<NaiveDateTime>>(4)?,
+                    applied_by: row.get(5)?,
+                    hostname: row.get(6)?,
+                    crude_version: row.get(7)?,
+                    duration_ms: row.get(8)?,
+                    seeded: row.get(9)?,
+                    environment: row.get(10)?,
+                },
             ))
         })?;
 
         let mut migrations = Vec::new();
 
         for row in rows {
-            let (name, hash, down_sql) = row?;
-            migrations.push(Migration::from_db(name, hash, down_sql)?);
+            let (name, hash, down_sql, applied) = row?;
+            let mut migration = Migration::from_db(name, hash, down_sql)?;
+            migration.applied = Some(applied);
+            migrations.push(migration);
         }
 
         Ok(migrations)
@@ -61,39 +380,65 @@ impl DatabaseAdapter for SqliteAdapter {
 
     fn run_up_migration(&mut self, migration: &Migration) -> Result<()> {
         let name = &migration.compound_name;
-        let hash = &migration.hash;
         let up_sql = migration.up_sql.as_ref().unwrap();
-        let down_sql = migration.down_sql.as_deref().unwrap_or("");
         let seed_sql = migration.seed_sql.as_deref();
 
-        // Check for no-transaction marker
-        let disable_tx = up_sql
-            .trim_start()
-            .to_lowercase()
-            .starts_with("-- no-transaction");
+        let autocommit = |sql: &str| autocommit_reason(sql, Dialect::Sqlite);
 
-        if disable_tx {
+        if !in_transaction(
+            name,
+            &migration.source("up.sql"),
+            up_sql,
+            Dialect::Sqlite,
+            migration.meta.transaction,
+            autocommit,
+        )? {
             // run up outside a transaction
-            self.conn.execute_batch(up_sql)?;
-            self.conn.execute(
-                "INSERT INTO crude_migrations (name, hash, down_sql) VALUES (?1, ?2, ?3)",
-                params![name, hash, down_sql],
+            let start = Instant::now();
+            run_statements(
+                name,
+                &migration.source("up.sql"),
+                up_sql,
+                Dialect::Sqlite,
+                |sql| Ok(self.conn.execute_batch(sql)?),
+            )?;
+            record_migration(
+                &self.conn,
+                migration,
+                elapsed_ms(start),
+                !self.tracking_ready,
             )?;
         } else {
             // run up + record inside a transaction
-            let tx = self.conn.transaction()?;
-            tx.execute_batch(up_sql)?;
-            tx.execute(
-                "INSERT INTO crude_migrations (name, hash, down_sql) VALUES (?1, ?2, ?3)",
-                params![name, hash, down_sql],
+            let tx = Savepoint::new(&self.conn)?;
+            let start = Instant::now();
+            run_statements(
+                name,
+                &migration.source("up.sql"),
+                up_sql,
+                Dialect::Sqlite,
+                |sql| Ok(tx.execute_batch(sql)?),
             )?;
+            record_migration(&tx, migration, elapsed_ms(start), !self.tracking_ready)?;
             tx.commit()?;
         }
 
+        self.tracking_ready = true;
+
         // always run seed in its own transaction if provided
         if let Some(seed) = seed_sql {
-            let tx = self.conn.transaction()?;
-            tx.execute_batch(seed)?;
+            let tx = Savepoint::new(&self.conn)?;
+            run_statements(
+                name,
+                &migration.source(&migration.seed_file),
+                seed,
+                Dialect::Sqlite,
+                |sql| Ok(tx.execute_batch(sql)?),
+            )?;
+            tx.execute(
+                "UPDATE crude_migrations SET seeded = TRUE WHERE name = ?1",
+                params![name],
+            )?;
             tx.commit()?;
         }
 
@@ -104,21 +449,36 @@ impl DatabaseAdapter for SqliteAdapter {
         let name = &migration.compound_name;
         let down_sql = migration.down_sql.as_ref().unwrap();
 
-        // Check for no-transaction marker
-        let disable_tx = down_sql
-            .trim_start()
-            .to_lowercase()
-            .starts_with("-- no-transaction");
+        let autocommit = |sql: &str| autocommit_reason(sql, Dialect::Sqlite);
 
-        if disable_tx {
-            self.conn.execute_batch(down_sql)?;
+        if !in_transaction(
+            name,
+            &migration.source("down.sql"),
+            down_sql,
+            Dialect::Sqlite,
+            migration.meta.transaction,
+            autocommit,
+        )? {
+            run_statements(
+                name,
+                &migration.source("down.sql"),
+                down_sql,
+                Dialect::Sqlite,
+                |sql| Ok(self.conn.execute_batch(sql)?),
+            )?;
             self.conn.execute(
                 "DELETE FROM crude_migrations WHERE name = ?1",
                 params![name],
             )?;
         } else {
-            let tx = self.conn.transaction()?;
-            tx.execute_batch(down_sql)?;
+            let tx = Savepoint::new(&self.conn)?;
+            run_statements(
+                name,
+                &migration.source("down.sql"),
+                down_sql,
+                Dialect::Sqlite,
+                |sql| Ok(tx.execute_batch(sql)?),
+            )?;
             tx.execute(
                 "DELETE FROM crude_migrations WHERE name = ?1",
                 params![name],
@@ -128,9 +488,69 @@ impl DatabaseAdapter for SqliteAdapter {
         Ok(())
     }
 
+    fn render_up_migration(&self, migration: &Migration) -> String {
+        render_up(migration, &render_record(migration), Dialect::Sqlite)
+    }
+
+    fn render_down_migration(&self, migration: &Migration) -> String {
+        let record = format!(
+            "DELETE FROM crude_migrations WHERE name = {};",
+            quote_literal(&migration.compound_name)
+        );
+
+        render_down(migration, &record, Dialect::Sqlite)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        // Migrations use savepoints, which nest inside this transaction
+        self.conn.execute_batch("BEGIN")?;
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")?;
+
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.conn.execute_batch("ROLLBACK")?;
+
+        Ok(())
+    }
+
+    fn set_timeouts(&mut self, statement: Option<Duration>, lock: Option<Duration>) -> Result<()> {
+        if statement.is_some() {
+            warn!("SQLite does not support statement timeouts, ignoring it");
+        }
+
+        match (lock, self.busy_timeout) {
+            (Some(lock), saved) => {
+                if saved.is_none() {
+                    self.busy_timeout = Some(self.conn.query_row(
+                        "PRAGMA busy_timeout",
+                        [],
+                        |row| row.get(0),
+                    )?);
+                }
+
+                self.conn.busy_timeout(lock)?;
+            }
+            (None, Some(millis)) => {
+                self.conn
+                    .busy_timeout(Duration::from_millis(millis.max(0) as u64))?;
+                self.busy_timeout = None;
+            }
+            (None, None) => {}
+        }
+
+        Ok(())
+    }
+
     fn update_migration_hash(&mut self, name: &str, hash: &str) -> Result<()> {
         self.conn.execute(
-            "UPDATE crude_migrations SET hash = ?1 WHERE name = ?2",
+            "UPDATE crude_migrations SET hash = ?1, updated_at = CURRENT_TIMESTAMP WHERE name = ?2",
             params![hash, name],
         )?;
 
@@ -139,67 +559,413 @@ impl DatabaseAdapter for SqliteAdapter {
 
     fn clear_migrations(&mut self) -> Result<()> {
         self.conn.execute_batch(
-            "DELETE FROM crude.migrations WHERE id > (SELECT MIN(id) FROM crude.migrations);",
+            "DELETE FROM crude_migrations WHERE id > (SELECT MIN(id) FROM crude_migrations);",
         )?;
 
         Ok(())
     }
 
     fn record_baseline(&mut self, name: &str, hash: &str) -> Result<()> {
+        let execution = Execution::current();
+
+        if !self.tracking_ready {
+            self.tracking_ready = upgrade_tracking_table(&self.conn)?;
+        }
+
+        self.conn.execute(
+            "INSERT INTO crude_migrations (name, hash, applied_by, hostname, crude_version)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                name,
+                hash,
+                execution.applied_by,
+                execution.hostname,
+                execution.crude_version
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn render_sync_rollup(&self, rollup: &Migration) -> String {
+        render_sync(
+            "crude_migrations",
+            "DELETE FROM crude_migrations WHERE id > (SELECT MIN(id) FROM crude_migrations);",
+            rollup,
+            quote_literal,
+        )
+    }
+
+    fn record_history(&mut self, entry: &HistoryEntry) -> Result<()> {
+        self.conn.execute_batch(HISTORY_TABLE_SQL)?;
+
         self.conn.execute(
-            "INSERT INTO crude_migrations (name, hash) VALUES (?1, ?2)",
-            params![name, hash],
+            "INSERT INTO crude_history
+            (event, name, hash, success, error, applied_by, hostname, crude_version, duration_ms)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                entry.event.as_str(),
+                entry.name,
+                entry.hash,
+                entry.success,
+                entry.error,
+                entry.applied_by,
+                entry.hostname,
+                entry.crude_version,
+                entry.duration_ms
+            ],
         )?;
 
         Ok(())
     }
 
-    fn dump_schema(&mut self, url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
-        // SQLite schema via sqlite3 .schema
-        let output = Command::new("sqlite3").arg(url).arg(".schema").output()?;
+    fn load_history(&mut self) -> Result<Vec<HistoryEntry>> {
+        let table_exists: bool = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'crude_history'",
+            params![],
+            |row| row.get(0),
+        )?;
 
-        if !output.status.success() {
-            return Err(eyre::eyre!(
-                "sqlite3 .schema failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+        if !table_exists {
+            return Ok(Vec::new());
         }
 
-        if exclude_migrations {
-            let schema = String::from_utf8_lossy(&output.stdout)
-                .lines()
-                .filter(|l| !l.contains("crude_migrations"))
-                .collect::<Vec<_>>()
-                .join("\n");
+        let mut stmt = self.conn.prepare(
+            "SELECT recorded_at, event, name, hash, success, error,
+                applied_by, hostname, crude_version, duration_ms
+            FROM crude_history ORDER BY id ASC",
+        )?;
 
-            Ok(schema.into_bytes())
-        } else {
-            Ok(output.stdout)
+        let entries = stmt
+            .query_map(params![], |row| {
+                let event = row
+                    .get::<_, String>(1)?
+                    .parse()
+                    .map_err(|e: eyre::Report| {
+                        rusqlite::Error::FromSqlConversionFailure(1, Type::Text, e.into())
+                    })?;
+
+                Ok(HistoryEntry {
+                    recorded_at: row.get(0)?,
+                    event,
+                    name: row.get(2)?,
+                    hash: row.get(3)?,
+                    success: row.get(4)?,
+                    error: row.get(5)?,
+                    applied_by: row.get(6)?,
+                    hostname: row.get(7)?,
+                    crude_version: row.get(8)?,
+                    duration_ms: row.get(9)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(entries)
+    }
+
+    fn load_repeatables(&mut self) -> Result<Vec<Repeatable>> {
+        let table_exists: bool = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'crude_repeatables'",
+            params![],
+            |row| row.get(0),
+        )?;
+
+        if !table_exists {
+            return Ok(Vec::new());
         }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT name, hash, applied_at, applied_by, hostname, crude_version, duration_ms
+            FROM crude_repeatables ORDER BY name ASC",
+        )?;
+
+        let repeatables = stmt
+            .query_map(params![], |row| {
+                Ok(Repeatable::from_db(
+                    row.get(0)?,
+                    row.get(1)?,
+                    Applied {
+                        applied_at: row.get(2)?,
+                        updated_at: None,
+                        applied_by: row.get(3)?,
+                        hostname: row.get(4)?,
+                        crude_version: row.get(5)?,
+                        duration_ms: row.get(6)?,
+                        seeded: None,
+                        environment: None,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(repeatables)
     }
 
-    fn dump_data(&mut self, url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
-        let output = Command::new("sqlite3").arg(url).arg(".dump").output()?;
+    fn run_repeatable(&mut self, repeatable: &Repeatable) -> Result<()> {
+        let name = repeatable.label();
+        let source = repeatable.source();
+        let sql = repeatable.sql.as_ref().unwrap();
 
-        if !output.status.success() {
-            return Err(eyre::eyre!(
-                "sqlite3 .dump failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+        self.conn.execute_batch(REPEATABLES_TABLE_SQL)?;
+
+        let autocommit = |sql: &str| autocommit_reason(sql, Dialect::Sqlite);
+
+        if !in_transaction(&name, &source, sql, Dialect::Sqlite, None, autocommit)? {
+            let start = Instant::now();
+            run_statements(&name, &source, sql, Dialect::Sqlite, |sql| {
+                Ok(self.conn.execute_batch(sql)?)
+            })?;
+            record_repeatable(&self.conn, repeatable, elapsed_ms(start))?;
+        } else {
+            let tx = Savepoint::new(&self.conn)?;
+            let start = Instant::now();
+            run_statements(&name, &source, sql, Dialect::Sqlite, |sql| {
+                Ok(tx.execute_batch(sql)?)
+            })?;
+            record_repeatable(&tx, repeatable, elapsed_ms(start))?;
+            tx.commit()?;
         }
 
-        if exclude_migrations {
-            let data = String::from_utf8_lossy(&output.stdout)
-                .lines()
-                .filter(|l| !l.contains("crude_migrations"))
+        Ok(())
+    }
+
+    fn render_repeatable(&self, repeatable: &Repeatable) -> String {
+        let record = format!(
+            "{REPEATABLES_TABLE_SQL}\n\
+            INSERT INTO crude_repeatables (name, hash) VALUES ({}, {})\n\
+            ON CONFLICT (name) DO UPDATE SET hash = excluded.hash, applied_at = CURRENT_TIMESTAMP;",
+            quote_literal(&repeatable.name),
+            quote_literal(&repeatable.hash),
+        );
+
+        render_repeat(repeatable, &record, Dialect::Sqlite)
+    }
+
+    fn dump_schema(&mut self, _url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
+        // Creation order keeps every object after the ones it depends on
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT sql FROM sqlite_master
+            WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' {}
+            ORDER BY rowid",
+            migrations_filter(exclude_migrations),
+        ))?;
+
+        let schema = stmt
+            .query_map(params![], |row| row.get::<_, String>(0))?
+            .map(|sql| sql.map(|sql| format!("{sql};\n")))
+            .collect::<rusqlite::Result<String>>()?;
+
+        Ok(schema.into_bytes())
+    }
+
+    fn dump_data(&mut self, _url: &str, exclude_migrations: bool) -> Result<Vec<u8>> {
+        let tables = self
+            .conn
+            .prepare(&format!(
+                "SELECT name FROM sqlite_master
+                WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' {}
+                ORDER BY name",
+                migrations_filter(exclude_migrations),
+            ))?
+            .query_map(params![], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut out = String::new();
+
+        for table in tables {
+            // Generated columns are hidden from table_info and can't be inserted into
+            let columns = self
+                .conn
+                .prepare("SELECT name FROM pragma_table_info(?1) ORDER BY cid")?
+                .query_map(params![table], |row| row.get::<_, String>(0))?
+                .map(|name| name.map(|name| quote_ident(&name)))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            if columns.is_empty() {
+                continue;
+            }
+
+            let select = columns
+                .iter()
+                .map(|column| format!("quote({column})"))
                 .collect::<Vec<_>>()
-                .join("\n");
+                .join(" || ', ' || ");
 
-            Ok(data.into_bytes())
-        } else {
-            Ok(output.stdout)
+            let mut rows = self
+                .conn
+                .prepare(&format!("SELECT {select} FROM {}", quote_ident(&table)))?
+                .query_map(params![], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            rows.sort();
+
+            for row in rows {
+                out.push_str(&format!(
+                    "INSERT INTO {} ({}) VALUES ({row});\n",
+                    quote_ident(&table),
+                    columns.join(", ")
+                ));
+            }
+        }
+
+        Ok(out.into_bytes())
+    }
+
+    fn backup(&mut self, _url: &str, path: &Path) -> Result<()> {
+        self.conn
+            .execute("VACUUM INTO ?1", params![path.to_string_lossy()])?;
+
+        Ok(())
+    }
+
+    fn restore(&mut self, _url: &str, path: &Path) -> Result<()> {
+        let has_history: bool = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'crude_history'",
+            params![],
+            |row| row.get(0),
+        )?;
+
+        // The temp database is left alone by the restore, keep the history there meanwhile
+        if has_history {
+            self.conn.execute_batch(
+                "DROP TABLE IF EXISTS temp.crude_history_kept;
+                CREATE TEMP TABLE crude_history_kept AS SELECT * FROM main.crude_history;",
+            )?;
+        }
+
+        self.conn
+            .restore(DatabaseName::Main, path, None::<fn(Progress)>)?;
+
+        if has_history {
+            self.conn.execute_batch(&format!(
+                "{HISTORY_TABLE_SQL}
+                DELETE FROM main.crude_history;
+                INSERT INTO main.crude_history SELECT * FROM temp.crude_history_kept;
+                DROP TABLE temp.crude_history_kept;"
+            ))?;
         }
+
+        Ok(())
+    }
+}
+
+/// Condition excluding the crude tables from `sqlite_master` queries.
+///
+/// The lock table is never part of the schema, it only holds the state of a run.
+fn migrations_filter(exclude_migrations: bool) -> String {
+    if exclude_migrations {
+        let tables = CRUDE_TABLES
+            .iter()
+            .map(|table| quote_literal(table))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!("AND tbl_name NOT IN ({tables})")
+    } else {
+        String::from("AND tbl_name <> 'crude_lock'")
+    }
+}
+
+/// Whether the process `pid` of this host has exited, as far as `/proc` tells.
+///
+/// Without `/proc`, the process is assumed to be running.
+fn process_gone(pid: i64) -> bool {
+    Path::new("/proc/self").exists() && !Path::new(&format!("/proc/{pid}")).exists()
+}
+
+/// Columns of the tracking table, used to detect tables created by older versions.
+fn tracking_columns(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('crude_migrations')")?;
+
+    let columns = stmt
+        .query_map(params![], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    Ok(columns)
+}
+
+/// Add execution detail columns missing from the tracking table, returning whether it exists.
+fn upgrade_tracking_table(conn: &Connection) -> Result<bool> {
+    let existing = tracking_columns(conn)?;
+
+    for sql in add_tracking_columns("crude_migrations", &existing, Dialect::Sqlite) {
+        conn.execute_batch(&sql)?;
     }
+
+    Ok(!existing.is_empty())
+}
+
+/// Render the record of an applied migration for a standalone SQL script.
+fn render_record(migration: &Migration) -> String {
+    let down_sql = quote_literal(migration.down_sql.as_deref().unwrap_or(""));
+
+    render_insert("crude_migrations", migration, &down_sql, quote_literal)
+}
+
+/// Record an applied migration along with details about this execution.
+fn record_migration(
+    conn: &Connection,
+    migration: &Migration,
+    duration_ms: i64,
+    upgrade: bool,
+) -> Result<()> {
+    let execution = Execution::current();
+
+    // The tracking table may have been created by this very migration
+    if upgrade {
+        upgrade_tracking_table(conn)?;
+    }
+
+    conn.execute(
+        "INSERT INTO crude_migrations
+        (name, hash, down_sql, applied_by, hostname, crude_version, duration_ms, seeded, environment)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, FALSE, ?8)",
+        params![
+            migration.compound_name,
+            migration.hash,
+            migration.down_sql.as_deref().unwrap_or(""),
+            execution.applied_by,
+            execution.hostname,
+            execution.crude_version,
+            duration_ms,
+            migration.environment
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Record the latest hash of a repeatable migration along with details about this execution.
+fn record_repeatable(conn: &Connection, repeatable: &Repeatable, duration_ms: i64) -> Result<()> {
+    let execution = Execution::current();
+
+    conn.execute(
+        "INSERT INTO crude_repeatables
+        (name, hash, applied_by, hostname, crude_version, duration_ms)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (name) DO UPDATE SET
+            hash = excluded.hash,
+            applied_at = CURRENT_TIMESTAMP,
+            applied_by = excluded.applied_by,
+            hostname = excluded.hostname,
+            crude_version = excluded.crude_version,
+            duration_ms = excluded.duration_ms",
+        params![
+            repeatable.name,
+            repeatable.hash,
+            execution.applied_by,
+            execution.hostname,
+            execution.crude_version,
+            duration_ms
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Quote an identifier with double quotes.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
 }
 
 /// DDL for creating the migrations table in SQLite.
@@ -210,6 +976,329 @@ CREATE TABLE crude_migrations (
     updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     name TEXT NOT NULL UNIQUE,
     hash TEXT NOT NULL,
-    down_sql TEXT
+    down_sql TEXT,
+    applied_by TEXT,
+    hostname TEXT,
+    crude_version TEXT,
+    duration_ms INTEGER,
+    seeded BOOLEAN,
+    environment TEXT
 );
 ";
+
+/// DDL for the append-only history of migration events in SQLite.
+const HISTORY_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS crude_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recorded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    event TEXT NOT NULL,
+    name TEXT NOT NULL,
+    hash TEXT,
+    success BOOLEAN NOT NULL,
+    error TEXT,
+    applied_by TEXT,
+    hostname TEXT,
+    crude_version TEXT,
+    duration_ms INTEGER
+);
+";
+
+/// DDL for the latest hash of each repeatable migration in SQLite.
+const REPEATABLES_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS crude_repeatables (
+    name TEXT PRIMARY KEY,
+    hash TEXT NOT NULL,
+    applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    applied_by TEXT,
+    hostname TEXT,
+    crude_version TEXT,
+    duration_ms INTEGER
+);
+";
+
+/// DDL for the single-row table holding the migration lock in SQLite.
+const LOCK_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS crude_lock (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    holder TEXT NOT NULL,
+    pid INTEGER NOT NULL,
+    hostname TEXT NOT NULL,
+    acquired_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_absolute_path() {
+        let url = SqliteUrl::parse("sqlite:///var/db/app.db").unwrap();
+
+        assert_eq!(url.path, Some(PathBuf::from("/var/db/app.db")));
+        assert_eq!(url.mode, SqliteMode::Create);
+    }
+
+    #[test]
+    fn test_parse_relative_path() {
+        let url = SqliteUrl::parse("sqlite://db/app.db").unwrap();
+        assert_eq!(url.path, Some(PathBuf::from("db/app.db")));
+
+        let url = SqliteUrl::parse("sqlite:app.db").unwrap();
+        assert_eq!(url.path, Some(PathBuf::from("app.db")));
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(SqliteUrl::parse("sqlite::memory:").unwrap().path, None);
+        assert_eq!(SqliteUrl::parse("sqlite://:memory:").unwrap().path, None);
+        assert_eq!(
+            SqliteUrl::parse("sqlite://x.db?mode=memory").unwrap().path,
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let url = SqliteUrl::parse(
+            "sqlite://app.db?mode=ro&journal_mode=WAL&busy_timeout=5000&foreign_keys=on",
+        )
+        .unwrap();
+
+        assert_eq!(url.mode, SqliteMode::ReadOnly);
+        assert_eq!(url.journal_mode.as_deref(), Some("wal"));
+        assert_eq!(url.busy_timeout, Some(Duration::from_millis(5000)));
+        assert_eq!(url.foreign_keys, Some(true));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(SqliteUrl::parse("sqlite://").is_err());
+        assert!(SqliteUrl::parse("sqlite://app.db?mode=bad").is_err());
+        assert!(SqliteUrl::parse("sqlite://app.db?journal_mode=x;DROP").is_err());
+        assert!(SqliteUrl::parse("sqlite://app.db?cache=shared").is_err());
+    }
+
+    #[test]
+    fn test_open_applies_pragmas() {
+        let conn = SqliteUrl::parse("sqlite::memory:?foreign_keys=on")
+            .unwrap()
+            .open()
+            .unwrap();
+
+        let foreign_keys: bool = conn
+            .query_row("PRAGMA foreign_keys", params![], |row| row.get(0))
+            .unwrap();
+
+        assert!(foreign_keys);
+    }
+
+    #[test]
+    fn test_lock_takes_over_from_gone_process() {
+        let mut db = SqliteAdapter::new(Connection::open_in_memory().unwrap());
+        db.conn.execute_batch(LOCK_TABLE_SQL).unwrap();
+
+        let hold = |db: &SqliteAdapter, pid: i64, hostname: &str| {
+            db.conn
+                .execute(
+                    "INSERT OR REPLACE INTO crude_lock (id, holder, pid, hostname)
+                    VALUES (1, 'someone', ?1, ?2)",
+                    params![pid, hostname],
+                )
+                .unwrap();
+        };
+
+        // A live process of this host, or any process of another host, keeps the lock
+        hold(
+            &db,
+            i64::from(std::process::id()),
+            &Execution::current().hostname,
+        );
+        assert!(db.lock(Duration::ZERO).is_err());
+
+        hold(&db, i64::from(u32::MAX), "elsewhere");
+        assert!(db.lock(Duration::ZERO).is_err());
+
+        if Path::new("/proc/self").exists() {
+            hold(&db, i64::from(u32::MAX), &Execution::current().hostname);
+            db.lock(Duration::ZERO).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_lock_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.db");
+        Connection::open(&path).unwrap();
+
+        let url = format!("sqlite://{}?mode=ro", path.display());
+        let mut db = SqliteAdapter::new(SqliteUrl::parse(&url).unwrap().open().unwrap());
+
+        db.lock(Duration::ZERO).unwrap();
+        db.unlock().unwrap();
+    }
+
+    #[test]
+    fn test_dump_round_trip() {
+        let mut db = SqliteAdapter::new(Connection::open_in_memory().unwrap());
+        db.conn.execute_batch(db.init_up_sql()).unwrap();
+        db.conn
+            .execute_batch(
+                r#"CREATE TABLE "order lines" (
+                    id INTEGER PRIMARY KEY,
+                    "it's" TEXT,
+                    qty INTEGER,
+                    total INTEGER GENERATED ALWAYS AS (qty * 2) VIRTUAL
+                );
+                CREATE INDEX lines_qty ON "order lines" (qty);
+                CREATE VIEW big_lines AS SELECT * FROM "order lines" WHERE qty > 1;
+                CREATE TABLE crude_notes (note TEXT);
+                INSERT INTO "order lines" (id, "it's", qty) VALUES (2, NULL, 3), (1, 'o''k', x'00ff');"#,
+            )
+            .unwrap();
+        db.record_baseline("20240101000000_init", "abc").unwrap();
+
+        let schema = String::from_utf8(db.dump_schema("", true).unwrap()).unwrap();
+        let data = String::from_utf8(db.dump_data("", true).unwrap()).unwrap();
+
+        // Only the tables of crude are left out, not those sharing their prefix
+        assert!(!schema.contains("crude_migrations"));
+        assert!(schema.contains("CREATE TABLE crude_notes"));
+        assert!(
+            schema.find("CREATE TABLE \"order lines\"") < schema.find("CREATE INDEX lines_qty")
+        );
+        assert!(schema.find("CREATE INDEX lines_qty") < schema.find("CREATE VIEW big_lines"));
+        assert_eq!(
+            data,
+            "INSERT INTO \"order lines\" (\"id\", \"it's\", \"qty\") VALUES (1, 'o''k', X'00FF');\n\
+            INSERT INTO \"order lines\" (\"id\", \"it's\", \"qty\") VALUES (2, NULL, 3);\n"
+        );
+
+        // The tracking table is only dumped when asked for
+        assert!(
+            String::from_utf8(db.dump_data("", false).unwrap())
+                .unwrap()
+                .contains("INSERT INTO \"crude_migrations\"")
+        );
+
+        let mut copy = SqliteAdapter::new(Connection::open_in_memory().unwrap());
+        copy.conn.execute_batch(&schema).unwrap();
+        copy.conn.execute_batch(&data).unwrap();
+
+        assert_eq!(copy.dump_schema("", true).unwrap(), schema.into_bytes());
+        assert_eq!(copy.dump_data("", true).unwrap(), data.into_bytes());
+    }
+
+    #[test]
+    fn test_clear_migrations() {
+        let mut db = SqliteAdapter::new(Connection::open_in_memory().unwrap());
+        db.conn.execute_batch(db.init_up_sql()).unwrap();
+
+        for name in [
+            "20240101000000_init",
+            "20240102000000_a",
+            "20240103000000_b",
+        ] {
+            db.record_baseline(name, "abc").unwrap();
+        }
+
+        db.clear_migrations().unwrap();
+
+        let names = db
+            .load_migrations()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.compound_name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec![String::from("20240101000000_init")]);
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        let mut db = SqliteAdapter::new(Connection::open_in_memory().unwrap());
+        db.conn.execute_batch(db.init_up_sql()).unwrap();
+
+        let mut migration = Migration::from_db(
+            String::from("20240101000000_broken"),
+            String::from("abc"),
+            None,
+        )
+        .unwrap();
+        migration.up_sql = Some(String::from(
+            "CREATE TABLE t (a int);\nSELECT * FROM missing;",
+        ));
+
+        assert!(db.run_up_migration(&migration).is_err());
+        assert!(db.conn.is_autocommit());
+        assert!(db.load_migrations().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_render_sync_rollup() {
+        let mut db = SqliteAdapter::new(Connection::open_in_memory().unwrap());
+        db.conn.execute_batch(db.init_up_sql()).unwrap();
+
+        for name in ["20240101000000_init", "20240102000000_a"] {
+            db.record_baseline(name, "abc").unwrap();
+        }
+
+        let rollup = Migration::from_db(
+            String::from("20240103000000_rollup"),
+            String::from("def"),
+            None,
+        )
+        .unwrap();
+
+        db.conn
+            .execute_batch(&db.render_sync_rollup(&rollup))
+            .unwrap();
+
+        let names = db
+            .load_migrations()
+            .unwrap()
+            .into_iter()
+            .map(|m| m.compound_name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            vec![
+                String::from("20240101000000_init"),
+                String::from("20240103000000_rollup")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_up_migration() {
+        let mut migration = Migration::from_db(
+            String::from("20240101000000_vacuum"),
+            String::from("abc"),
+            None,
+        )
+        .unwrap();
+        migration.up_sql = Some(String::from("VACUUM;"));
+        migration.seed_sql = Some(String::from("INSERT INTO t VALUES (1);"));
+        migration.meta.transaction = Some(false);
+
+        let execution = Execution::current();
+
+        // Outside a transaction, the seed and the record still share one
+        assert_eq!(
+            render_up(&migration, &render_record(&migration), Dialect::Sqlite),
+            format!(
+                "VACUUM;\n\n\
+                BEGIN;\n\n\
+                INSERT INTO t VALUES (1);\n\n\
+                INSERT INTO crude_migrations\n\
+                (name, hash, down_sql, applied_by, hostname, crude_version, duration_ms, seeded, environment)\n\
+                VALUES ('20240101000000_vacuum', 'abc', '', {}, {}, {}, NULL, TRUE, NULL);\n\n\
+                COMMIT;\n",
+                quote_literal(&execution.applied_by),
+                quote_literal(&execution.hostname),
+                quote_literal(execution.crude_version),
+            )
+        );
+    }
+}
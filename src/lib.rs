@@ -1,24 +1,35 @@
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
 use anstream::eprintln;
 use clap::Parser;
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 use colorchoice_clap::Color;
+use eyre::eyre;
 use owo_colors::OwoColorize;
 use proc_exit::Code;
 
 use crate::{
     commands::Subcommands,
+    config::{CONFIG_FILE, Config},
     error::{Result, exit},
+    migration::lint::{Level, Rule},
 };
 
+mod backup;
+mod config;
 mod db;
 mod migration;
 
 pub mod error;
+mod output;
+mod protect;
+mod sql;
 mod styles;
 
 pub mod commands;
 
 pub use migration::planner::PlanOptions;
+pub use output::OutputFormat;
 
 /// Migration toolkit for databases
 #[derive(Debug, Parser)]
@@ -38,30 +49,86 @@ pub struct App {
     pub options: Options,
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 #[clap(next_help_heading = "Global Options")]
 pub struct Options {
     /// Database URL
     #[arg(short, long, env = "DATABASE_URL")]
     pub url: Option<String>,
 
-    /// Directory containing migrations
-    #[arg(
-        short = 'd',
-        long,
-        default_value = "./db/migrations",
-        env = "MIGRATIONS_DIR"
-    )]
+    /// Directory containing migrations [default: ./db/migrations]
+    #[arg(short = 'd', long, env = "MIGRATIONS_DIR")]
     pub migrations_dir: Option<String>,
 
     /// File to dump the schema to
     #[arg(short, long, env = "SCHEMA_FILE")]
     pub schema: Option<String>,
+
+    /// Dump the schema from the catalog instead of using pg_dump
+    #[arg(long, env = "NATIVE_DUMP")]
+    pub native_dump: bool,
+
+    /// Seconds to wait for the migration lock held by another process [default: 60]
+    #[arg(long, env = "LOCK_WAIT")]
+    pub lock_wait: Option<u64>,
+
+    /// Milliseconds a statement of a migration may run before it is cancelled
+    #[arg(long, value_name = "MS", env = "CRUDE_STATEMENT_TIMEOUT")]
+    pub statement_timeout: Option<u64>,
+
+    /// Milliseconds a migration may wait for a table or row lock before giving up
+    #[arg(long, value_name = "MS", env = "CRUDE_LOCK_TIMEOUT")]
+    pub lock_timeout: Option<u64>,
+
+    /// Times to retry a migration that timed out waiting for a lock [default: 3]
+    #[arg(long, value_name = "N", env = "CRUDE_LOCK_RETRIES")]
+    pub lock_retries: Option<u32>,
+
+    /// Environment to migrate, skips migrations limited to other environments
+    #[arg(short, long, env = "CRUDE_ENV")]
+    pub env: Option<String>,
+
+    /// Set a variable substituted for `${NAME}` in migrations
+    ///
+    /// Migrations are only substituted once a variable is defined, `$${NAME}` then
+    /// stands for a literal `${NAME}`.
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    pub vars: Vec<String>,
+
+    /// TOML file of variables substituted in migrations
+    #[arg(long, value_name = "FILE", env = "CRUDE_VAR_FILE")]
+    pub var_file: Option<String>,
+
+    /// Project configuration file [default: crude.toml in this or a parent directory]
+    #[arg(long, value_name = "FILE", env = "CRUDE_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Profile of the configuration file to use
+    #[arg(long, env = "CRUDE_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Ask before rolling back, repairing or rolling up migrations
+    #[arg(long, env = "CRUDE_PROTECTED")]
+    pub protected: bool,
+
+    /// Skip the confirmation of destructive changes to protected databases
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// Levels of lint rules, from the `[lint]` table of the configuration file
+    #[arg(skip)]
+    pub lint: BTreeMap<Rule, Level>,
+
+    /// Back up the database to DIR before rolling back or running migrations outside a transaction
+    #[arg(long, value_name = "DIR", env = "CRUDE_BACKUP_DIR")]
+    pub backup_dir: Option<String>,
 }
 
 impl App {
     pub fn run(self) -> Result {
-        self.cmd.run(&self.options)
+        let options = self.options.with_config()?;
+
+        self.cmd.run(&options)
     }
 
     pub fn new(cmd: Subcommands, options: Options) -> Self {
@@ -75,6 +142,27 @@ impl App {
 }
 
 impl Options {
+    /// Fill options that were not given from the configuration file, if any.
+    pub fn with_config(self) -> Result<Self> {
+        let config = match &self.config {
+            Some(path) => Some(Config::load(path)?),
+            None => Config::discover()?,
+        };
+
+        match config {
+            Some(config) => config.apply(self),
+            None if self.profile.is_some() => {
+                Err(eyre!("--profile requires a {CONFIG_FILE}, none was found"))
+            }
+            None => Ok(self),
+        }
+    }
+
+    /// How long to wait for the migration lock
+    pub fn lock_wait(&self) -> Duration {
+        Duration::from_secs(self.lock_wait.unwrap_or(60))
+    }
+
     /// Get the database URL or error out if not provided
     pub fn get_url(&self) -> Result<&str> {
         if let Some(url) = self.url.as_ref() {
@@ -1,12 +1,20 @@
 use std::{
     fmt::{Display, Formatter, Result as FmtResult},
-    fs::{create_dir_all, read_dir, remove_dir_all, rename, write},
-    path::PathBuf,
+    fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename, write},
+    path::{Path, PathBuf},
 };
 
 use eyre::eyre;
+use tracing::warn;
 
-use crate::{Options, error::Result, migration::Migration};
+use crate::{
+    Options,
+    error::Result,
+    migration::{Migration, Repeatable},
+};
+
+/// Subdirectory holding repeatable migrations, one `.sql` file each.
+pub const REPEATABLE_DIR: &str = "repeatable";
 
 /// Manages filesystem operations for local migrations.
 pub struct MigrationsDir {
@@ -52,25 +60,67 @@ impl MigrationsDir {
         Ok(())
     }
 
-    /// Load local migrations from subdirectories (sorted by name).
+    /// Load local migrations from subdirectories and single `.sql` files (sorted by name).
     pub fn load(&self) -> Result<Vec<Migration>> {
         self.check()?;
 
-        let mut migrations = Vec::new();
-
-        let mut dirs = read_dir(&self.dir)?
+        let mut migrations = read_dir(&self.dir)?
             .flatten()
-            .filter(|e| e.file_type().is_ok_and(|ft| ft.is_dir()))
-            .collect::<Vec<_>>();
+            .map(|e| e.path())
+            .filter(|p| p.file_name().is_some_and(|n| n != REPEATABLE_DIR))
+            .filter_map(|p| {
+                if p.is_dir() {
+                    Some(Migration::from_dir(&p))
+                } else if is_sql_file(&p) {
+                    if is_migration_file(&p) {
+                        Some(Migration::from_file(&p))
+                    } else {
+                        // Such as a schema dump kept next to the migrations
+                        warn!(
+                            "skipping {}, it is not named <timestamp>_<name>.sql",
+                            p.display()
+                        );
+
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        migrations.sort_by(|a, b| a.compound_name.cmp(&b.compound_name));
+
+        if let Some(pair) = migrations
+            .windows(2)
+            .find(|pair| pair[0].compound_name == pair[1].compound_name)
+        {
+            return Err(eyre!(
+                "migration {} exists both as a directory and as a single file",
+                pair[0].compound_name
+            ));
+        }
+
+        Ok(migrations)
+    }
 
-        dirs.sort_by_key(|e| e.file_name());
+    /// Load repeatable migrations from the `repeatable` subdirectory (sorted by name).
+    pub fn load_repeatables(&self) -> Result<Vec<Repeatable>> {
+        let dir = self.dir.join(REPEATABLE_DIR);
 
-        for entry in dirs {
-            let mig = Migration::from_dir(&entry.path())?;
-            migrations.push(mig);
+        if !dir.exists() {
+            return Ok(Vec::new());
         }
 
-        Ok(migrations)
+        let mut files = read_dir(&dir)?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| is_sql_file(p))
+            .collect::<Vec<_>>();
+
+        files.sort();
+
+        files.iter().map(|p| Repeatable::from_file(p)).collect()
     }
 
     /// Write a new migration folder and blank SQL files.
@@ -91,19 +141,48 @@ impl MigrationsDir {
         Ok(())
     }
 
+    /// Write a new single-file migration with blank sections.
+    pub fn create_single_file_migration(&self, compound_name: &String) -> Result<()> {
+        write(
+            self.dir.join(format!("{compound_name}.sql")),
+            "-- +up\n\n-- +down\n\n-- +seed\n",
+        )?;
+
+        Ok(())
+    }
+
+    /// Path of a migration, either its directory or its single file.
+    fn migration_path(&self, compound_name: &String) -> PathBuf {
+        let file = self.dir.join(format!("{compound_name}.sql"));
+
+        if file.is_file() {
+            file
+        } else {
+            self.dir.join(compound_name)
+        }
+    }
+
     /// Remove a migration by its compound name.
     pub fn remove_migration(&self, compound_name: &String) -> Result<()> {
-        let path = self.dir.join(compound_name);
+        let path = self.migration_path(compound_name);
 
-        remove_dir_all(&path)?;
+        if path.is_dir() {
+            remove_dir_all(&path)?;
+        } else {
+            remove_file(&path)?;
+        }
 
         Ok(())
     }
 
     /// Rename a migration
     pub fn rename_migration(&self, from: &String, to: &String) -> Result<()> {
-        let from_path = self.dir.join(from);
-        let to_path = self.dir.join(to);
+        let from_path = self.migration_path(from);
+        let to_path = if from_path.is_dir() {
+            self.dir.join(to)
+        } else {
+            self.dir.join(format!("{to}.sql"))
+        };
 
         rename(from_path, to_path)?;
 
@@ -111,9 +190,42 @@ impl MigrationsDir {
     }
 }
 
+fn is_sql_file(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|ext| ext == "sql")
+}
+
+/// Whether a `.sql` file is named like a single-file migration.
+fn is_migration_file(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| Migration::from_compound_name(&n.to_string()).is_ok())
+}
+
 /// Build a MigrationsDir from CLI options.
 pub fn get_migrations_dir(opts: &Options) -> MigrationsDir {
     let dir = opts.migrations_dir.as_deref().unwrap_or("./db/migrations");
 
     MigrationsDir::new(dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_skips_other_sql_files() {
+        let dir = tempfile::tempdir().unwrap();
+
+        write(
+            dir.path().join("20240101000000_users.sql"),
+            "-- +up\nSELECT 1;\n",
+        )
+        .unwrap();
+        write(dir.path().join("schema.sql"), "CREATE TABLE t (a int);\n").unwrap();
+
+        let migrations = MigrationsDir::new(dir.path()).load().unwrap();
+
+        assert_eq!(migrations.len(), 1);
+        assert_eq!(migrations[0].compound_name, "20240101000000_users");
+    }
+}
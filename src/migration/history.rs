@@ -0,0 +1,224 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+    time::Instant,
+};
+
+use anstream::println;
+use chrono::NaiveDateTime;
+use eyre::eyre;
+use owo_colors::OwoColorize;
+use serde::{Serialize, Serializer};
+use tracing::warn;
+
+use crate::{
+    db::{DatabaseAdapter, Execution, elapsed_ms},
+    error::Result,
+    output::{OutputFormat, print_records},
+};
+
+/// Kind of change recorded in the migration history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    /// A migration was applied.
+    Up,
+    /// A migration was rolled back.
+    Down,
+    /// The hash of an applied migration was updated.
+    Repair,
+    /// The tracking table was reset to a rollup created elsewhere.
+    SyncRollup,
+    /// The tracking table was reset to a new rollup.
+    Baseline,
+    /// A repeatable migration was (re-)applied.
+    Repeatable,
+    /// The database was restored from a backup.
+    Restore,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Up => "up",
+            Event::Down => "down",
+            Event::Repair => "repair",
+            Event::SyncRollup => "sync-rollup",
+            Event::Baseline => "baseline",
+            Event::Repeatable => "repeatable",
+            Event::Restore => "restore",
+        }
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Event {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "up" => Ok(Event::Up),
+            "down" => Ok(Event::Down),
+            "repair" => Ok(Event::Repair),
+            "sync-rollup" => Ok(Event::SyncRollup),
+            "baseline" => Ok(Event::Baseline),
+            "repeatable" => Ok(Event::Repeatable),
+            "restore" => Ok(Event::Restore),
+            _ => Err(eyre!("unknown history event {s}")),
+        }
+    }
+}
+
+/// A single entry of the append-only migration history.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    /// When the entry was recorded, set by the database.
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub recorded_at: Option<NaiveDateTime>,
+    pub event: Event,
+    /// Compound name of the migration.
+    pub name: String,
+    /// Hash of the migration at the time of the event.
+    pub hash: Option<String>,
+    /// Whether the operation succeeded.
+    pub success: bool,
+    /// Error message of a failed operation.
+    pub error: Option<String>,
+    pub applied_by: Option<String>,
+    pub hostname: Option<String>,
+    pub crude_version: Option<String>,
+    pub duration_ms: Option<i64>,
+}
+
+impl HistoryEntry {
+    /// Describe an operation performed by this process.
+    pub fn new(
+        event: Event,
+        name: &str,
+        hash: Option<&str>,
+        result: &Result<()>,
+        duration_ms: i64,
+    ) -> Self {
+        let execution = Execution::current();
+
+        HistoryEntry {
+            recorded_at: None,
+            event,
+            name: name.to_string(),
+            hash: hash.map(String::from),
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+            applied_by: Some(execution.applied_by),
+            hostname: Some(execution.hostname),
+            crude_version: Some(execution.crude_version.to_string()),
+            duration_ms: Some(duration_ms),
+        }
+    }
+}
+
+/// Serialize timestamps the same way as in `status` output.
+fn serialize_timestamp<S: Serializer>(
+    timestamp: &Option<NaiveDateTime>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match timestamp {
+        Some(t) => serializer.serialize_str(&t.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Run `op` and append its outcome to the migration history.
+///
+/// Failing to record a failed operation is only logged, so that the original
+/// error is the one reported.
+pub fn record(
+    db: &mut Box<dyn DatabaseAdapter>,
+    event: Event,
+    name: &str,
+    hash: Option<&str>,
+    op: impl FnOnce(&mut Box<dyn DatabaseAdapter>) -> Result<()>,
+) -> Result<()> {
+    let start = Instant::now();
+    let result = op(db);
+    let entry = HistoryEntry::new(event, name, hash, &result, elapsed_ms(start));
+
+    match (result, db.record_history(&entry)) {
+        (Ok(()), recorded) => recorded,
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(record_err)) => {
+            warn!("unable to record {event} of {name} in the history: {record_err}");
+
+            Err(err)
+        }
+    }
+}
+
+/// Print history entries, oldest first.
+pub fn print_history(entries: &[HistoryEntry], format: OutputFormat) -> Result<()> {
+    if format != OutputFormat::Text {
+        return print_records(entries, format);
+    }
+
+    for entry in entries {
+        let recorded_at = entry
+            .recorded_at
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+
+        let event = if entry.success {
+            format!("{:>11}", entry.event.as_str().green())
+        } else {
+            format!("{:>11}", entry.event.as_str().red())
+        };
+
+        let by = match (&entry.applied_by, &entry.hostname) {
+            (Some(user), Some(host)) => format!(" by {user}@{host}"),
+            (Some(user), None) => format!(" by {user}"),
+            _ => String::new(),
+        };
+
+        let duration = entry
+            .duration_ms
+            .map(|ms| format!(" in {ms}ms"))
+            .unwrap_or_default();
+
+        println!(
+            "{recorded_at} {event} - {}{}",
+            entry.name,
+            format!("{by}{duration}").dimmed()
+        );
+
+        for line in entry.error.iter().flat_map(|e| e.lines()) {
+            println!("{:34}{}", "", line.red());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_round_trip() {
+        for event in [
+            Event::Up,
+            Event::Down,
+            Event::Repair,
+            Event::SyncRollup,
+            Event::Baseline,
+            Event::Repeatable,
+            Event::Restore,
+        ] {
+            assert_eq!(event.as_str().parse::<Event>().unwrap(), event);
+        }
+
+        assert!("sideways".parse::<Event>().is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    migration::{Migration, Source},
+    sql::{Dialect, Statement, location, snippet, split},
+};
+
+/// A risky pattern flagged by `crude lint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// Adding a NOT NULL column without a default fails on tables with rows.
+    NotNullWithoutDefault,
+    /// Creating an index without CONCURRENTLY blocks writes to the table (Postgres).
+    NonConcurrentIndex,
+    DropColumn,
+    DropTable,
+    /// Changing the type of a column can rewrite the table and break code.
+    ColumnTypeChange,
+    /// Renaming a table or column breaks code still using the old name.
+    Rename,
+    MissingDown,
+}
+
+impl Rule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rule::NotNullWithoutDefault => "not-null-without-default",
+            Rule::NonConcurrentIndex => "non-concurrent-index",
+            Rule::DropColumn => "drop-column",
+            Rule::DropTable => "drop-table",
+            Rule::ColumnTypeChange => "column-type-change",
+            Rule::Rename => "rename",
+            Rule::MissingDown => "missing-down",
+        }
+    }
+
+    /// Level of the rule unless configured otherwise.
+    pub fn default_level(&self) -> Level {
+        match self {
+            Rule::NonConcurrentIndex | Rule::ColumnTypeChange | Rule::MissingDown => Level::Warning,
+            _ => Level::Error,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Rule::NotNullWithoutDefault => {
+                "adding a NOT NULL column without a default fails on tables with rows"
+            }
+            Rule::NonConcurrentIndex => {
+                "creating an index without CONCURRENTLY blocks writes to the table until it is built"
+            }
+            Rule::DropColumn => "dropping a column loses its data and breaks code still reading it",
+            Rule::DropTable => "dropping a table loses its data and breaks code still using it",
+            Rule::ColumnTypeChange => {
+                "changing the type of a column may rewrite the table and break code using it"
+            }
+            Rule::Rename => "renaming breaks code still using the old name",
+            Rule::MissingDown => "the migration has no down.sql and cannot be rolled back",
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a rule is reported, set per rule in the `[lint]` table of `crude.toml`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Off,
+    Warning,
+    Error,
+}
+
+/// A rule broken by a migration.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub migration: String,
+    pub rule: Rule,
+    pub level: Level,
+    pub message: &'static str,
+    /// File and 1-based line and column of the statement, e.g. `up.sql:3:1`.
+    pub location: String,
+    #[serde(skip)]
+    pub snippet: Option<String>,
+}
+
+/// Check a migration against the rules, with the levels configured in `levels`.
+///
+/// Rules listed in `lint_allow` of the migration's metadata are skipped. Statements
+/// on tables created earlier in the same file are not flagged, as nothing uses them yet.
+pub fn lint(
+    migration: &Migration,
+    dialect: Dialect,
+    levels: &BTreeMap<Rule, Level>,
+) -> Vec<Finding> {
+    let level = |rule: Rule| {
+        let level = levels.get(&rule).copied().unwrap_or(rule.default_level());
+
+        (level != Level::Off && !migration.meta.lint_allow.contains(&rule)).then_some(level)
+    };
+
+    let mut findings = Vec::new();
+
+    for (file, sql) in [
+        ("up.sql", &migration.up_sql),
+        ("down.sql", &migration.down_sql),
+    ] {
+        let Some(sql) = sql else { continue };
+        let source = migration.source(file);
+        let mut created = HashSet::new();
+
+        for statement in split(sql, dialect) {
+            for rule in check(&statement, dialect, &mut created) {
+                // A down.sql is expected to drop what its up.sql created
+                if file == "down.sql" && matches!(rule, Rule::DropColumn | Rule::DropTable) {
+                    continue;
+                }
+
+                if let Some(level) = level(rule) {
+                    findings.push(finding(
+                        migration,
+                        rule,
+                        level,
+                        &source,
+                        sql,
+                        statement.offset,
+                    ));
+                }
+            }
+        }
+    }
+
+    let missing_down = migration.down_sql.is_none() && !migration.meta.irreversible;
+
+    if let Some(level) = level(Rule::MissingDown).filter(|_| missing_down) {
+        let path = migration.path.as_ref().map_or_else(
+            || migration.compound_name.clone(),
+            |p| p.display().to_string(),
+        );
+
+        findings.push(Finding {
+            migration: migration.compound_name.clone(),
+            rule: Rule::MissingDown,
+            level,
+            message: Rule::MissingDown.message(),
+            location: path,
+            snippet: None,
+        });
+    }
+
+    findings
+}
+
+fn finding(
+    migration: &Migration,
+    rule: Rule,
+    level: Level,
+    source: &Source,
+    sql: &str,
+    offset: usize,
+) -> Finding {
+    let (line, column) = location(sql, offset);
+
+    Finding {
+        migration: migration.compound_name.clone(),
+        rule,
+        level,
+        message: rule.message(),
+        location: format!("{source}:{}:{column}", line + source.line_offset),
+        snippet: Some(snippet(sql, offset, source.line_offset)),
+    }
+}
+
+/// Rules broken by a statement, recording the tables it creates in `created`.
+fn check(statement: &Statement, dialect: Dialect, created: &mut HashSet<String>) -> Vec<Rule> {
+    let sql = statement.sql;
+    let mut rules = Vec::new();
+
+    let create_table =
+        Regex::new(r#"(?is)^CREATE\s+(?:TEMP\w*\s+)?TABLE\s+(?:IF\s+NOT\s+EXISTS\s+)?([\w."`]+)"#)
+            .unwrap();
+
+    if let Some(c) = create_table.captures(sql) {
+        created.insert(table_name(&c[1]));
+
+        return rules;
+    }
+
+    let alter_table =
+        Regex::new(r#"(?is)^ALTER\s+TABLE\s+(?:IF\s+EXISTS\s+)?(?:ONLY\s+)?([\w."`]+)"#).unwrap();
+    let create_index =
+        Regex::new(r#"(?is)^CREATE\s+(?:UNIQUE\s+)?INDEX\b.*?\bON\s+(?:ONLY\s+)?([\w."`]+)"#)
+            .unwrap();
+
+    if let Some(c) = alter_table.captures(sql) {
+        if created.contains(&table_name(&c[1])) {
+            return rules;
+        }
+
+        let add_column =
+            Regex::new(r"(?is)\bADD\s+(?:COLUMN\s+)?(?:IF\s+NOT\s+EXISTS\s+)?(\w+)\b[^,]*")
+                .unwrap();
+        let not_null = Regex::new(r"(?i)\bNOT\s+NULL\b").unwrap();
+        let default = Regex::new(r"(?i)\bDEFAULT\b").unwrap();
+
+        if add_column
+            .captures_iter(sql)
+            .any(|c| !is_constraint(&c[1]) && not_null.is_match(&c[0]) && !default.is_match(&c[0]))
+        {
+            rules.push(Rule::NotNullWithoutDefault);
+        }
+
+        let drop = Regex::new(r"(?i)\bDROP\s+(COLUMN\s+)?(?:IF\s+EXISTS\s+)?(\w+)").unwrap();
+
+        if drop
+            .captures_iter(sql)
+            .any(|c| c.get(1).is_some() || !is_constraint(&c[2]))
+        {
+            rules.push(Rule::DropColumn);
+        }
+
+        let type_change = Regex::new(
+            r"(?i)\bALTER\s+(?:COLUMN\s+)?\w+\s+(?:SET\s+DATA\s+)?TYPE\b|\b(?:MODIFY|CHANGE)\s+(?:COLUMN\s+)?\w+",
+        )
+        .unwrap();
+
+        if type_change.is_match(sql) {
+            rules.push(Rule::ColumnTypeChange);
+        }
+
+        if Regex::new(r"(?i)\bRENAME\b").unwrap().is_match(sql) {
+            rules.push(Rule::Rename);
+        }
+    } else if let Some(c) = create_index.captures(sql) {
+        let concurrently = Regex::new(r"(?i)\bCONCURRENTLY\b").unwrap();
+
+        if dialect == Dialect::Postgres
+            && !concurrently.is_match(sql)
+            && !created.contains(&table_name(&c[1]))
+        {
+            rules.push(Rule::NonConcurrentIndex);
+        }
+    } else if Regex::new(r"(?i)^DROP\s+TABLE\b").unwrap().is_match(sql) {
+        rules.push(Rule::DropTable);
+    } else if Regex::new(r"(?i)^RENAME\s+TABLE\b").unwrap().is_match(sql) {
+        rules.push(Rule::Rename);
+    }
+
+    rules
+}
+
+/// Table name without quotes, in lower case.
+fn table_name(name: &str) -> String {
+    name.replace(['"', '`'], "").to_lowercase()
+}
+
+/// Whether the word after ADD or DROP names something other than a column.
+fn is_constraint(word: &str) -> bool {
+    matches!(
+        word.to_ascii_uppercase().as_str(),
+        "CONSTRAINT"
+            | "PRIMARY"
+            | "UNIQUE"
+            | "FOREIGN"
+            | "CHECK"
+            | "INDEX"
+            | "KEY"
+            | "DEFAULT"
+            | "NOT"
+            | "EXPRESSION"
+            | "IDENTITY"
+            | "PARTITION"
+            | "TRIGGER"
+            | "VALUE"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(sql: &str, dialect: Dialect) -> Vec<Rule> {
+        let mut created = HashSet::new();
+
+        split(sql, dialect)
+            .iter()
+            .flat_map(|s| check(s, dialect, &mut created))
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let pg = Dialect::Postgres;
+
+        assert_eq!(
+            rules("ALTER TABLE users ADD COLUMN age int NOT NULL;", pg),
+            vec![Rule::NotNullWithoutDefault]
+        );
+        assert!(
+            rules(
+                "ALTER TABLE users ADD COLUMN age int NOT NULL DEFAULT 0;",
+                pg
+            )
+            .is_empty()
+        );
+        assert!(
+            rules(
+                "ALTER TABLE users ADD CONSTRAINT c CHECK (a IS NOT NULL);",
+                pg
+            )
+            .is_empty()
+        );
+        assert_eq!(
+            rules("CREATE INDEX i ON users (email);", pg),
+            vec![Rule::NonConcurrentIndex]
+        );
+        assert!(rules("CREATE INDEX CONCURRENTLY i ON users (email);", pg).is_empty());
+        assert!(rules("CREATE INDEX i ON users (email);", Dialect::Sqlite).is_empty());
+        assert_eq!(
+            rules("ALTER TABLE users DROP COLUMN age;", pg),
+            vec![Rule::DropColumn]
+        );
+        assert!(rules("ALTER TABLE users ALTER COLUMN age DROP NOT NULL;", pg).is_empty());
+        assert_eq!(rules("DROP TABLE users;", pg), vec![Rule::DropTable]);
+        assert_eq!(
+            rules("ALTER TABLE users ALTER COLUMN age TYPE bigint;", pg),
+            vec![Rule::ColumnTypeChange]
+        );
+        assert_eq!(
+            rules("ALTER TABLE users MODIFY age bigint;", Dialect::Mysql),
+            vec![Rule::ColumnTypeChange]
+        );
+        assert_eq!(
+            rules("ALTER TABLE users RENAME COLUMN a TO b;", pg),
+            vec![Rule::Rename]
+        );
+        assert_eq!(
+            rules("RENAME TABLE a TO b;", Dialect::Mysql),
+            vec![Rule::Rename]
+        );
+
+        // Tables created in the same file have no rows nor users yet
+        assert!(
+            rules(
+                "CREATE TABLE users (id int);
+                ALTER TABLE users ADD COLUMN age int NOT NULL;
+                CREATE INDEX i ON users (age);",
+                pg
+            )
+            .is_empty()
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::{fs::read_to_string, path::Path, time::Duration};
+
+use eyre::eyre;
+use serde::Deserialize;
+
+use crate::{error::Result, migration::lint::Rule};
+
+/// File holding the metadata of a migration, inside its directory.
+pub const METADATA_FILE: &str = "migration.toml";
+
+/// Optional settings and documentation of a migration, read from its `migration.toml`.
+///
+/// Single-file migrations have them in a `-- +meta` section instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metadata {
+    /// What the migration does.
+    pub description: Option<String>,
+    /// Who wrote the migration.
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    /// Whether to run inside a transaction, detected from the statements when unset.
+    pub transaction: Option<bool>,
+    /// Milliseconds a single statement may run before it is cancelled.
+    pub statement_timeout_ms: Option<u64>,
+    /// Milliseconds to wait for a lock on a table or row before giving up.
+    pub lock_timeout_ms: Option<u64>,
+    /// Environments the migration applies to, all of them when empty.
+    ///
+    /// Entries starting with `!` exclude an environment instead, e.g. `["!production"]`.
+    pub environments: Vec<String>,
+    /// Acknowledges that the migration cannot be rolled back.
+    pub irreversible: bool,
+    /// Lint rules not to check on the migration.
+    pub lint_allow: Vec<Rule>,
+}
+
+impl Metadata {
+    /// Read the `migration.toml` of a migration directory, if it has one.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let path = dir.join(METADATA_FILE);
+
+        if !path.exists() {
+            return Ok(Metadata::default());
+        }
+
+        let contents =
+            read_to_string(&path).map_err(|e| eyre!("unable to read {}: {}", path.display(), e))?;
+
+        Self::parse(&contents, &path.display().to_string())
+    }
+
+    /// Parse metadata written as TOML, `origin` tells where it comes from in errors.
+    pub fn parse(contents: &str, origin: &str) -> Result<Self> {
+        toml::from_str(contents).map_err(|e| eyre!("invalid {origin}: {e}"))
+    }
+
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn lock_timeout(&self) -> Option<Duration> {
+        self.lock_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Whether the migration applies to `env`.
+    ///
+    /// Migrations limited to some environments don't apply when no environment is given.
+    pub fn applies_to(&self, env: Option<&str>) -> bool {
+        let (excluded, included): (Vec<_>, Vec<_>) =
+            self.environments.iter().partition(|e| e.starts_with('!'));
+
+        if excluded.iter().any(|e| Some(&e[1..]) == env) {
+            return false;
+        }
+
+        included.is_empty() || included.iter().any(|e| Some(e.as_str()) == env)
+    }
+
+    /// Short summary for `status --long`, empty when there is nothing to show.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+
+        if let Some(description) = &self.description {
+            parts.push(description.clone());
+        }
+
+        if let Some(author) = &self.author {
+            parts.push(format!("by {author}"));
+        }
+
+        if !self.tags.is_empty() {
+            parts.push(format!("[{}]", self.tags.join(", ")));
+        }
+
+        if !self.environments.is_empty() {
+            parts.push(format!("environments {}", self.environments.join(", ")));
+        }
+
+        if self.irreversible {
+            parts.push(String::from("irreversible"));
+        }
+
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        let meta: Metadata = toml::from_str(
+            r#"
+            description = "Add users"
+            tags = ["auth"]
+            transaction = false
+            lock_timeout_ms = 5000
+            environments = ["dev", "staging"]
+            irreversible = true
+            lint_allow = ["drop-column"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(meta.description.as_deref(), Some("Add users"));
+        assert_eq!(meta.transaction, Some(false));
+        assert_eq!(meta.lock_timeout(), Some(Duration::from_secs(5)));
+        assert_eq!(meta.statement_timeout(), None);
+        assert_eq!(meta.lint_allow, vec![Rule::DropColumn]);
+        assert_eq!(
+            meta.describe(),
+            "Add users [auth] environments dev, staging irreversible"
+        );
+
+        assert!(toml::from_str::<Metadata>("transactional = true").is_err());
+    }
+
+    #[test]
+    fn test_applies_to() {
+        let meta = |environments: &[&str]| Metadata {
+            environments: environments.iter().map(|e| e.to_string()).collect(),
+            ..Metadata::default()
+        };
+
+        assert!(meta(&[]).applies_to(None));
+        assert!(meta(&[]).applies_to(Some("production")));
+
+        assert!(meta(&["dev", "staging"]).applies_to(Some("dev")));
+        assert!(!meta(&["dev", "staging"]).applies_to(Some("production")));
+        assert!(!meta(&["dev", "staging"]).applies_to(None));
+
+        assert!(meta(&["!production"]).applies_to(Some("dev")));
+        assert!(meta(&["!production"]).applies_to(None));
+        assert!(!meta(&["!production"]).applies_to(Some("production")));
+    }
+}
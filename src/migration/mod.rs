@@ -1,13 +1,24 @@
-use std::{fs::read_to_string, path::Path};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
 
 use chrono::{DateTime, NaiveDateTime, Utc};
 use eyre::eyre;
 use sha2::{Digest, Sha256};
 
-use crate::error::Result;
+use crate::{
+    error::Result,
+    migration::{metadata::Metadata, vars::Variables},
+};
 
 pub mod dir;
+pub mod history;
+pub mod lint;
+pub mod metadata;
 pub mod planner;
+pub mod vars;
 
 /// Represents a migration, either loaded locally or from the database.
 #[derive(Debug, Clone)]
@@ -16,6 +27,8 @@ pub struct Migration {
     pub name: String,
     /// Compound name, e.g. "20230623041234_create_users".
     pub compound_name: String,
+    /// Timestamp prefix of the compound name.
+    pub timestamp: DateTime<Utc>,
     /// Contents of the `up.sql`, if available.
     pub up_sql: Option<String>,
     /// Contents of the `down.sql`, if available.
@@ -24,9 +37,85 @@ pub struct Migration {
     pub seed_sql: Option<String>,
     /// SHA256 hash (hex) of the `up.sql`, if available.
     pub hash: String,
+    /// Execution details, for migrations loaded from the database.
+    pub applied: Option<Applied>,
+    /// Directory (or single file) of a local migration, used to point at errors in its files.
+    pub path: Option<PathBuf>,
+    /// Where each section starts, for migrations written as a single file.
+    pub sections: Option<Sections>,
+    /// Settings from the `migration.toml` of a local migration.
+    pub meta: Metadata,
+    /// File the seed SQL was read from, e.g. "seed.dev.sql".
+    pub seed_file: String,
+    /// Environment the migration is applied in, recorded in the tracking table.
+    pub environment: Option<String>,
+}
+
+/// Line offsets of the sections of a single-file migration.
+#[derive(Debug, Clone, Default)]
+pub struct Sections {
+    pub up: usize,
+    pub down: usize,
+    pub seed: usize,
+}
+
+/// Where a piece of migration SQL comes from, used to point at errors in it.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub path: String,
+    /// Number of lines preceding the SQL in the file.
+    pub line_offset: usize,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(&self.path)
+    }
+}
+
+/// Execution details recorded in the tracking table when a migration was applied.
+///
+/// Fields are optional because older tracking tables don't have the columns.
+#[derive(Debug, Clone, Default)]
+pub struct Applied {
+    pub applied_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+    pub applied_by: Option<String>,
+    pub hostname: Option<String>,
+    pub crude_version: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub seeded: Option<bool>,
+    pub environment: Option<String>,
 }
 
 impl Migration {
+    /// Describe where one of the SQL files of this migration comes from.
+    ///
+    /// `file` is the name of the file in a migration directory, e.g. "up.sql".
+    pub fn source(&self, file: &str) -> Source {
+        match (&self.path, &self.sections) {
+            (Some(path), Some(sections)) => Source {
+                path: path.display().to_string(),
+                line_offset: match file {
+                    "up.sql" => sections.up,
+                    "down.sql" => sections.down,
+                    _ => sections.seed,
+                },
+            },
+            (Some(path), None) => Source {
+                path: path.join(file).display().to_string(),
+                line_offset: 0,
+            },
+            (None, _) => Source {
+                path: format!(
+                    "{} of {} (recorded in the database)",
+                    file, self.compound_name
+                ),
+                line_offset: 0,
+            },
+        }
+    }
+
     fn from_compound_name(compound_name: &String) -> Result<(String, DateTime<Utc>)> {
         let underscore = compound_name
             .find('_')
@@ -50,15 +139,13 @@ impl Migration {
             .ok_or_else(|| eyre!("invalid migration directory {}", path.display()))?
             .to_string();
 
-        let (name, _) = Self::from_compound_name(&compound_name)?;
+        let (name, timestamp) = Self::from_compound_name(&compound_name)?;
 
         let up_path = path.join("up.sql");
         let up_sql = read_to_string(&up_path)
             .map_err(|e| eyre!("unable to read migration {}: {}", up_path.display(), e))?;
 
-        let mut hasher = Sha256::new();
-        hasher.update(up_sql.as_bytes());
-        let hash = hex::encode(hasher.finalize());
+        let hash = hash_sql(&up_sql);
 
         let down_sql = read_to_string(path.join("down.sql"))
             .ok()
@@ -71,24 +158,378 @@ impl Migration {
         Ok(Migration {
             name,
             compound_name,
+            timestamp,
             up_sql: Some(up_sql),
             down_sql,
             seed_sql,
             hash,
+            applied: None,
+            path: Some(path.to_path_buf()),
+            sections: None,
+            meta: Metadata::from_dir(path)?,
+            seed_file: String::from("seed.sql"),
+            environment: None,
+        })
+    }
+
+    /// Apply the migration in `env`, using its `seed.<env>.sql` instead of `seed.sql` if it has one.
+    ///
+    /// Single-file migrations have a `-- +seed <env>` section instead.
+    pub fn set_environment(&mut self, env: Option<&str>) -> Result<()> {
+        self.environment = env.map(String::from);
+
+        let (Some(env), Some(path)) = (env, &self.path) else {
+            return Ok(());
+        };
+
+        let seed_file = format!("seed.{env}.sql");
+
+        if let Some(sections) = &mut self.sections {
+            let contents = read_to_string(path)
+                .map_err(|e| eyre!("unable to read migration {}: {}", path.display(), e))?;
+            let parsed = split_sections(&contents)
+                .map_err(|e| eyre!("invalid migration {}: {}", path.display(), e))?;
+
+            let marker = format!("seed {env}");
+
+            if let Some((_, line, sql)) = parsed.into_iter().find(|(m, _, _)| *m == marker) {
+                sections.seed = line;
+                self.seed_sql = Some(sql).filter(|s| !s.trim().is_empty());
+                self.seed_file = seed_file;
+            }
+
+            return Ok(());
+        }
+
+        let seed_path = path.join(&seed_file);
+
+        if seed_path.exists() {
+            let seed_sql = read_to_string(&seed_path)
+                .map_err(|e| eyre!("unable to read migration {}: {}", seed_path.display(), e))?;
+
+            self.seed_sql = Some(seed_sql).filter(|s| !s.is_empty());
+            self.seed_file = seed_file;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the `${name}` placeholders in the SQL of a local migration.
+    ///
+    /// The hash is left untouched, it always covers the SQL as written in the files.
+    /// SQL recorded in the database was already substituted when it was applied.
+    pub fn substitute(&mut self, vars: &Variables) -> Result<()> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+
+        if let Some(sql) = &self.up_sql {
+            self.up_sql =
+                Some(vars.substitute(&self.compound_name, &self.source("up.sql"), sql)?);
+        }
+
+        if let Some(sql) = &self.down_sql {
+            self.down_sql =
+                Some(vars.substitute(&self.compound_name, &self.source("down.sql"), sql)?);
+        }
+
+        if let Some(sql) = &self.seed_sql {
+            self.seed_sql =
+                Some(vars.substitute(&self.compound_name, &self.source(&self.seed_file), sql)?);
+        }
+
+        Ok(())
+    }
+
+    /// Load a local migration from a single `<compound_name>.sql` file.
+    ///
+    /// The file is split in sections by `-- +up`, `-- +down` and `-- +seed` lines, with
+    /// `-- +seed <env>` sections for the seeds of environments. Settings that a migration
+    /// directory keeps in its `migration.toml` go in a `-- +meta` section, written as TOML.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let compound_name = path
+            .file_stem()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| eyre!("invalid migration file {}", path.display()))?
+            .to_string();
+
+        let (name, timestamp) = Self::from_compound_name(&compound_name)?;
+
+        let contents = read_to_string(path)
+            .map_err(|e| eyre!("unable to read migration {}: {}", path.display(), e))?;
+
+        let parsed = split_sections(&contents)
+            .map_err(|e| eyre!("invalid migration {}: {}", path.display(), e))?;
+
+        let mut sections = Sections::default();
+        let section = |marker: &str, offset: &mut usize| {
+            parsed
+                .iter()
+                .find(|(m, _, _)| *m == marker)
+                .map(|(_, line, sql)| {
+                    *offset = *line;
+                    sql.clone()
+                })
+        };
+
+        let up_sql = section("up", &mut sections.up)
+            .ok_or_else(|| eyre!("migration {} has no `-- +up` section", path.display()))?;
+        let down_sql = section("down", &mut sections.down).filter(|s| !s.trim().is_empty());
+        let seed_sql = section("seed", &mut sections.seed).filter(|s| !s.trim().is_empty());
+
+        let meta = match section("meta", &mut 0) {
+            Some(meta) => {
+                Metadata::parse(&meta, &format!("`-- +meta` section of {}", path.display()))?
+            }
+            None => Metadata::default(),
+        };
+
+        Ok(Migration {
+            name,
+            compound_name,
+            timestamp,
+            hash: hash_sql(&up_sql),
+            up_sql: Some(up_sql),
+            down_sql,
+            seed_sql,
+            applied: None,
+            path: Some(path.to_path_buf()),
+            sections: Some(sections),
+            meta,
+            seed_file: String::from("seed.sql"),
+            environment: None,
         })
     }
 
     /// Construct a migration record from database metadata.
     pub fn from_db(compound_name: String, hash: String, down_sql: Option<String>) -> Result<Self> {
-        let (name, _) = Self::from_compound_name(&compound_name)?;
+        let (name, timestamp) = Self::from_compound_name(&compound_name)?;
 
         Ok(Migration {
             name,
             compound_name,
+            timestamp,
             up_sql: None,
             down_sql,
             seed_sql: None,
             hash,
+            applied: None,
+            path: None,
+            sections: None,
+            meta: Metadata::default(),
+            seed_file: String::from("seed.sql"),
+            environment: None,
+        })
+    }
+}
+
+/// A repeatable migration, re-applied after versioned ones whenever its file changes.
+#[derive(Debug, Clone)]
+pub struct Repeatable {
+    /// File stem, e.g. "views" for `repeatable/views.sql`.
+    pub name: String,
+    /// Contents of the file, if available.
+    pub sql: Option<String>,
+    /// SHA256 hash (hex) of the file.
+    pub hash: String,
+    /// Execution details, for repeatables loaded from the database.
+    pub applied: Option<Applied>,
+    /// Path of a local repeatable.
+    pub path: Option<PathBuf>,
+}
+
+impl Repeatable {
+    /// Load a local repeatable migration from a file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let name = path
+            .file_stem()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| eyre!("invalid repeatable migration {}", path.display()))?
+            .to_string();
+
+        let sql = read_to_string(path)
+            .map_err(|e| eyre!("unable to read migration {}: {}", path.display(), e))?;
+
+        Ok(Repeatable {
+            name,
+            hash: hash_sql(&sql),
+            sql: Some(sql),
+            applied: None,
+            path: Some(path.to_path_buf()),
         })
     }
+
+    /// Construct a repeatable record from database metadata.
+    pub fn from_db(name: String, hash: String, applied: Applied) -> Self {
+        Repeatable {
+            name,
+            sql: None,
+            hash,
+            applied: Some(applied),
+            path: None,
+        }
+    }
+
+    /// Replace the `${name}` placeholders in the SQL of a local repeatable, keeping its hash.
+    pub fn substitute(&mut self, vars: &Variables) -> Result<()> {
+        if let Some(sql) = &self.sql {
+            self.sql = Some(vars.substitute(&self.label(), &self.source(), sql)?);
+        }
+
+        Ok(())
+    }
+
+    /// Name shown in plans and errors, e.g. "repeatable/views".
+    pub fn label(&self) -> String {
+        format!("repeatable/{}", self.name)
+    }
+
+    /// Describe where the SQL of this repeatable comes from.
+    pub fn source(&self) -> Source {
+        Source {
+            path: match &self.path {
+                Some(path) => path.display().to_string(),
+                None => self.label(),
+            },
+            line_offset: 0,
+        }
+    }
+}
+
+/// Split a single-file migration by its `-- +up`, `-- +down`, `-- +seed [<env>]` and
+/// `-- +meta` lines.
+///
+/// Returns the marker, e.g. "up" or "seed dev", the number of lines preceding the SQL,
+/// and the SQL of each section.
+fn split_sections(contents: &str) -> Result<Vec<(String, usize, String)>> {
+    let mut parsed: Vec<(String, usize, String)> = Vec::new();
+
+    for (i, line) in contents.split_inclusive('\n').enumerate() {
+        let marker = line.trim().strip_prefix("-- +").and_then(|rest| {
+            let mut words = rest.split_whitespace();
+            let keyword = words.next()?.to_lowercase();
+
+            match (keyword.as_str(), words.next(), words.next()) {
+                ("up" | "down" | "seed" | "meta", None, _) => Some(keyword),
+                ("seed", Some(env), None) => Some(format!("seed {env}")),
+                _ => None,
+            }
+        });
+
+        if let Some(marker) = marker {
+            if parsed.iter().any(|(m, _, _)| *m == marker) {
+                return Err(eyre!("duplicate `-- +{marker}` section at line {}", i + 1));
+            }
+
+            parsed.push((marker, i + 1, String::new()));
+            continue;
+        }
+
+        match parsed.last_mut() {
+            Some((_, _, sql)) => sql.push_str(line),
+            None if line.trim().is_empty() => {}
+            None => {
+                return Err(eyre!(
+                    "SQL at line {} comes before the first section",
+                    i + 1
+                ));
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// SHA256 hash (hex) of a migration file.
+pub fn hash_sql(sql: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(sql.as_bytes());
+
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use super::*;
+
+    #[test]
+    fn test_split_sections() {
+        let contents = "\n-- +up\nCREATE TABLE t (a int);\n\n-- +DOWN\nDROP TABLE t;\n";
+
+        assert_eq!(
+            split_sections(contents).unwrap(),
+            vec![
+                (
+                    String::from("up"),
+                    2,
+                    String::from("CREATE TABLE t (a int);\n\n")
+                ),
+                (String::from("down"), 5, String::from("DROP TABLE t;\n")),
+            ]
+        );
+
+        let err = split_sections("SELECT 1;\n-- +up\n").unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+
+        let err = split_sections("-- +up\n-- +down\n-- +up\n").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("duplicate `-- +up` section at line 3")
+        );
+    }
+
+    #[test]
+    fn test_from_file_meta() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("20240101000000_users.sql");
+
+        write(
+            &path,
+            "-- +meta\ntransaction = false\nenvironments = [\"dev\"]\n\n-- +up\nCREATE TABLE t (a int);\n",
+        )
+        .unwrap();
+
+        let migration = Migration::from_file(&path).unwrap();
+
+        assert_eq!(migration.meta.transaction, Some(false));
+        assert_eq!(migration.meta.environments, vec![String::from("dev")]);
+        assert_eq!(
+            migration.up_sql.as_deref(),
+            Some("CREATE TABLE t (a int);\n")
+        );
+
+        write(&path, "-- +meta\ntransactions = false\n-- +up\nSELECT 1;\n").unwrap();
+
+        let err = Migration::from_file(&path).unwrap_err();
+        assert!(err.to_string().contains("invalid `-- +meta` section"));
+    }
+
+    #[test]
+    fn test_from_file_environment_seed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("20240101000000_users.sql");
+
+        write(
+            &path,
+            "-- +up\nCREATE TABLE t (a int);\n-- +seed\nINSERT INTO t VALUES (1);\n-- +seed dev\nINSERT INTO t VALUES (2);\n",
+        )
+        .unwrap();
+
+        let mut migration = Migration::from_file(&path).unwrap();
+        migration.set_environment(Some("prod")).unwrap();
+        assert_eq!(
+            migration.seed_sql.as_deref(),
+            Some("INSERT INTO t VALUES (1);\n")
+        );
+
+        migration.set_environment(Some("dev")).unwrap();
+        assert_eq!(
+            migration.seed_sql.as_deref(),
+            Some("INSERT INTO t VALUES (2);\n")
+        );
+        assert_eq!(migration.sections.as_ref().unwrap().seed, 5);
+        assert_eq!(migration.seed_file, "seed.dev.sql");
+    }
 }
@@ -2,22 +2,38 @@ use std::{
     cmp::min,
     collections::HashMap,
     fmt::{Display, Formatter, Result as FmtResult},
+    fs::write,
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
 };
 
-use anstream::{print, println};
+use anstream::{eprint, print, println};
 use clap::Parser;
 use eyre::eyre;
 use owo_colors::OwoColorize;
+use serde::Serialize;
+use tracing::{debug, warn};
 
 use crate::{
-    Options,
-    db::{DatabaseAdapter, get_db_adapter},
+    Options, backup,
+    db::{DatabaseAdapter, MigrationError, Timeout, elapsed_ms, render_in_transaction},
     error::Result,
-    migration::{Migration, dir::get_migrations_dir},
+    migration::{
+        Applied, Migration, Repeatable,
+        dir::get_migrations_dir,
+        history::{Event, HistoryEntry, record},
+        metadata::METADATA_FILE,
+        vars::Variables,
+    },
+    output::{OutputFormat, print_records},
+    protect,
+    sql::Dialect,
 };
 
 /// The state of a migration when comparing local vs. database.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MigrationState {
     Applied,
     Pending,
@@ -30,6 +46,133 @@ pub enum MigrationState {
 pub struct Status {
     pub state: MigrationState,
     pub migration: Migration,
+    /// Hash of the local `up.sql`, if the migration exists locally.
+    pub local_hash: Option<String>,
+    /// Hash recorded in the database, if the migration was applied.
+    pub remote_hash: Option<String>,
+    /// Execution details recorded in the database, if the migration was applied.
+    pub applied: Option<Applied>,
+}
+
+/// The state of a repeatable migration when comparing local vs. database.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatableState {
+    /// Applied with the current contents of the file.
+    Applied,
+    /// Never applied.
+    Pending,
+    /// Applied with different contents, it will be re-applied.
+    Changed,
+    /// Applied, but the file no longer exists.
+    Removed,
+}
+
+/// Status entry for a single repeatable migration.
+#[derive(Debug, Clone)]
+pub struct RepeatableStatus {
+    pub state: RepeatableState,
+    pub repeatable: Repeatable,
+    /// Hash of the local file, if it exists.
+    pub local_hash: Option<String>,
+    /// Latest hash recorded in the database, if it was applied.
+    pub remote_hash: Option<String>,
+    /// Execution details of the latest application, if it was applied.
+    pub applied: Option<Applied>,
+}
+
+/// State of either kind of migration in machine-readable output.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum RecordState<'a> {
+    Versioned(&'a MigrationState),
+    Repeatable(&'a RepeatableState),
+}
+
+/// Machine-readable form of a [`Status`] or [`RepeatableStatus`].
+#[derive(Debug, Serialize)]
+struct StatusRecord<'a> {
+    state: RecordState<'a>,
+    compound_name: &'a str,
+    name: &'a str,
+    timestamp: Option<String>,
+    local_hash: Option<&'a str>,
+    remote_hash: Option<&'a str>,
+    has_down: bool,
+    applied_at: Option<String>,
+    updated_at: Option<String>,
+    applied_by: Option<&'a str>,
+    hostname: Option<&'a str>,
+    crude_version: Option<&'a str>,
+    duration_ms: Option<i64>,
+    seeded: Option<bool>,
+    environment: Option<&'a str>,
+    repeatable: bool,
+    description: Option<&'a str>,
+    author: Option<&'a str>,
+    tags: &'a [String],
+    environments: &'a [String],
+    irreversible: bool,
+}
+
+impl<'a> From<&'a Status> for StatusRecord<'a> {
+    fn from(status: &'a Status) -> Self {
+        let applied = status.applied.as_ref();
+
+        StatusRecord {
+            state: RecordState::Versioned(&status.state),
+            compound_name: &status.migration.compound_name,
+            name: &status.migration.name,
+            timestamp: Some(status.migration.timestamp.to_rfc3339()),
+            local_hash: status.local_hash.as_deref(),
+            remote_hash: status.remote_hash.as_deref(),
+            has_down: status.migration.down_sql.is_some(),
+            applied_at: applied.and_then(|a| a.applied_at).map(|t| t.to_string()),
+            updated_at: applied.and_then(|a| a.updated_at).map(|t| t.to_string()),
+            applied_by: applied.and_then(|a| a.applied_by.as_deref()),
+            hostname: applied.and_then(|a| a.hostname.as_deref()),
+            crude_version: applied.and_then(|a| a.crude_version.as_deref()),
+            duration_ms: applied.and_then(|a| a.duration_ms),
+            seeded: applied.and_then(|a| a.seeded),
+            environment: applied.and_then(|a| a.environment.as_deref()),
+            repeatable: false,
+            description: status.migration.meta.description.as_deref(),
+            author: status.migration.meta.author.as_deref(),
+            tags: &status.migration.meta.tags,
+            environments: &status.migration.meta.environments,
+            irreversible: status.migration.meta.irreversible,
+        }
+    }
+}
+
+impl<'a> From<&'a RepeatableStatus> for StatusRecord<'a> {
+    fn from(status: &'a RepeatableStatus) -> Self {
+        let applied = status.applied.as_ref();
+
+        StatusRecord {
+            state: RecordState::Repeatable(&status.state),
+            compound_name: &status.repeatable.name,
+            name: &status.repeatable.name,
+            timestamp: None,
+            local_hash: status.local_hash.as_deref(),
+            remote_hash: status.remote_hash.as_deref(),
+            has_down: false,
+            applied_at: applied.and_then(|a| a.applied_at).map(|t| t.to_string()),
+            updated_at: None,
+            applied_by: applied.and_then(|a| a.applied_by.as_deref()),
+            hostname: applied.and_then(|a| a.hostname.as_deref()),
+            crude_version: applied.and_then(|a| a.crude_version.as_deref()),
+            duration_ms: applied.and_then(|a| a.duration_ms),
+            seeded: None,
+            environment: None,
+            repeatable: true,
+            description: None,
+            author: None,
+            tags: &[],
+            environments: &[],
+            irreversible: false,
+        }
+    }
 }
 
 /// A single step in a migration plan.
@@ -37,6 +180,84 @@ pub struct Status {
 pub enum PlanStep {
     Up(Migration),
     Down(Migration),
+    Repeat(Repeatable),
+    /// Reset the tracking table to a rollup baseline.
+    Sync(Migration),
+}
+
+impl PlanStep {
+    /// Whether the step runs inside a transaction.
+    fn in_transaction(&self, dialect: Dialect) -> bool {
+        let (sql, transaction) = match self {
+            PlanStep::Up(m) => (&m.up_sql, m.meta.transaction),
+            PlanStep::Down(m) => (&m.down_sql, m.meta.transaction),
+            PlanStep::Repeat(r) => (&r.sql, None),
+            PlanStep::Sync(_) => return true,
+        };
+
+        render_in_transaction(sql.as_deref().unwrap_or_default(), dialect, transaction)
+    }
+
+    /// Whether the step destroys data, or could leave changes behind if it fails.
+    fn needs_backup(&self, dialect: Dialect) -> bool {
+        match self {
+            PlanStep::Down(_) => true,
+            // Only the tracking table changes
+            PlanStep::Sync(_) => false,
+            // MySQL commits schema changes implicitly
+            _ if dialect == Dialect::Mysql => true,
+            _ => !self.in_transaction(dialect),
+        }
+    }
+
+    /// Statement and lock timeouts of the step, its metadata overriding those of the run.
+    fn timeouts(&self, run: &Timeouts) -> (Option<Duration>, Option<Duration>) {
+        match self {
+            PlanStep::Up(m) | PlanStep::Down(m) => (
+                m.meta.statement_timeout().or(run.statement),
+                m.meta.lock_timeout().or(run.lock),
+            ),
+            PlanStep::Repeat(_) | PlanStep::Sync(_) => (run.statement, run.lock),
+        }
+    }
+
+    /// Run the step, without recording it in the history.
+    fn execute(
+        &self,
+        db: &mut Box<dyn DatabaseAdapter>,
+        options: &PlanOptions,
+        timeouts: &Timeouts,
+    ) -> Result<()> {
+        let (statement, lock) = self.timeouts(timeouts);
+
+        with_timeouts(db, statement, lock, |db| match self {
+            PlanStep::Down(m) => db.run_down_migration(m),
+            PlanStep::Up(m) => {
+                let mut m = m.clone();
+
+                if !options.seed {
+                    m.seed_sql = None;
+                }
+
+                db.run_up_migration(&m)
+            }
+            PlanStep::Repeat(r) => db.run_repeatable(r),
+            PlanStep::Sync(m) => {
+                db.clear_migrations()?;
+                db.record_baseline(&m.compound_name, &m.hash)
+            }
+        })
+    }
+
+    /// Event, name and hash of the step in the history.
+    fn event(&self) -> (Event, String, &str) {
+        match self {
+            PlanStep::Up(m) => (Event::Up, m.compound_name.clone(), &m.hash),
+            PlanStep::Down(m) => (Event::Down, m.compound_name.clone(), &m.hash),
+            PlanStep::Repeat(r) => (Event::Repeatable, r.label(), &r.hash),
+            PlanStep::Sync(m) => (Event::SyncRollup, m.compound_name.clone(), &m.hash),
+        }
+    }
 }
 
 impl Display for PlanStep {
@@ -44,25 +265,59 @@ impl Display for PlanStep {
         match self {
             PlanStep::Up(m) => write!(f, "{:>4} - {}", "Up".green(), m.compound_name),
             PlanStep::Down(m) => write!(f, "{:>4} - {}", "Down".red(), m.compound_name),
+            PlanStep::Repeat(r) => write!(f, "{:>4} - {}", "Repeat".cyan(), r.label()),
+            PlanStep::Sync(m) => write!(f, "{:>4} - {}", "Sync".cyan(), m.compound_name),
         }
     }
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Default, Parser)]
 pub struct PlanOptions {
-    /// Run seed.sql after applying migrations
+    /// Run seed.sql (or seed.<env>.sql with --env) after applying migrations
     #[clap(long, env = "SEED")]
     pub seed: bool,
 
     /// Only show the migration plan without applying it
     #[clap(short, long)]
     pub plan_only: bool,
+
+    /// Write the plan as a SQL script to FILE ("-" for stdout) without applying it
+    #[clap(long, value_name = "FILE", conflicts_with = "plan_only")]
+    pub script: Option<String>,
+
+    /// Run the whole plan in a single transaction, rolling it all back on failure
+    ///
+    /// Not supported on MySQL, which commits schema changes implicitly.
+    #[clap(long, conflicts_with = "script")]
+    pub atomic: bool,
 }
 
 /// A plan of migrations to apply or rollback.
 #[derive(Debug, Clone)]
 pub struct Plan {
     pub steps: Vec<PlanStep>,
+    /// Backup taken before running the plan, if any.
+    pub backup: Option<PathBuf>,
+    pub timeouts: Timeouts,
+}
+
+/// Timeouts of the migrations of a run, which a migration may override in its metadata.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    pub statement: Option<Duration>,
+    pub lock: Option<Duration>,
+    /// Times to retry a migration after a lock timeout.
+    pub lock_retries: u32,
+}
+
+impl Timeouts {
+    pub fn from_options(opts: &Options) -> Self {
+        Timeouts {
+            statement: opts.statement_timeout.map(Duration::from_millis),
+            lock: opts.lock_timeout.map(Duration::from_millis),
+            lock_retries: opts.lock_retries.unwrap_or(3),
+        }
+    }
 }
 
 impl Display for Plan {
@@ -78,27 +333,285 @@ impl Display for Plan {
 impl Plan {
     /// Run the plan with the given options
     pub fn run(&self, db: &mut Box<dyn DatabaseAdapter>, options: &PlanOptions) -> Result<()> {
-        if options.plan_only {
+        if let Some(path) = &options.script {
+            let script = self.render(db.as_ref(), options);
+
+            if path == "-" {
+                print!("{script}");
+            } else {
+                write(path, script)?;
+
+                debug!("plan written to {path}");
+            }
+        } else if options.plan_only {
             print!("{self}");
+        } else if options.atomic {
+            self.run_atomic(db, options)?;
         } else {
             for step in &self.steps {
-                match step.clone() {
-                    PlanStep::Down(m) => db.run_down_migration(&m)?,
-                    PlanStep::Up(mut m) => {
-                        if !options.seed {
-                            m.seed_sql = None;
-                        }
-
-                        db.run_up_migration(&m)?;
-                    }
+                let result = self.run_step(db, step, options);
+
+                if let (Err(_), Some(path)) = (&result, &self.backup) {
+                    warn!(
+                        "the database was backed up to {} before running the plan, `crude restore` puts it back",
+                        path.display()
+                    );
                 }
 
+                result?;
+
                 println!("{step}");
             }
         }
 
         Ok(())
     }
+
+    /// Run and record a step, retrying with a growing delay while it times out waiting for a lock.
+    fn run_step(
+        &self,
+        db: &mut Box<dyn DatabaseAdapter>,
+        step: &PlanStep,
+        options: &PlanOptions,
+    ) -> Result<()> {
+        let (event, name, hash) = step.event();
+
+        // Only a failed transaction is sure to leave nothing behind to retry over
+        let retries = if step.in_transaction(db.dialect()) {
+            self.timeouts.lock_retries
+        } else {
+            0
+        };
+        let mut attempt = 0;
+
+        loop {
+            let result = record(db, event, &name, Some(hash), |db| {
+                step.execute(db, options, &self.timeouts)
+            });
+
+            match result {
+                Err(err) if attempt < retries && timeout_of(&err) == Some(Timeout::Lock) => {
+                    attempt += 1;
+
+                    let delay = Duration::from_secs(1 << (attempt - 1).min(5));
+
+                    warn!(
+                        "{name} timed out waiting for a lock, retrying in {}s ({attempt} of {retries})",
+                        delay.as_secs()
+                    );
+                    sleep(delay);
+                }
+                result => {
+                    return result.map_err(|err| self.explain_timeout(step, err, attempt + 1));
+                }
+            }
+        }
+    }
+
+    /// Add which timeout cancelled the step to its error, if one did.
+    fn explain_timeout(&self, step: &PlanStep, err: eyre::Report, attempts: u32) -> eyre::Report {
+        let (_, name, _) = step.event();
+        let (statement, lock) = step.timeouts(&self.timeouts);
+
+        match (timeout_of(&err), statement, lock) {
+            (Some(Timeout::Lock), _, Some(lock)) => eyre!(
+                "{err}\n{name} timed out waiting for a lock {attempts} time(s), with a lock timeout of {}ms",
+                lock.as_millis()
+            ),
+            (Some(Timeout::Lock), _, None) => {
+                eyre!("{err}\n{name} timed out waiting for a lock {attempts} time(s)")
+            }
+            (Some(Timeout::Statement), Some(statement), _) => eyre!(
+                "{err}\n{name} was cancelled by the statement timeout of {}ms",
+                statement.as_millis()
+            ),
+            _ => err,
+        }
+    }
+
+    /// Run every step in a single transaction, committed only if all of them succeed.
+    fn run_atomic(&self, db: &mut Box<dyn DatabaseAdapter>, options: &PlanOptions) -> Result<()> {
+        let dialect = db.dialect();
+
+        // A rollback would leave every schema change before the failure behind
+        if dialect == Dialect::Mysql {
+            return Err(eyre!(
+                "cannot run the plan atomically on MySQL, which commits schema changes implicitly"
+            ));
+        }
+
+        if let Some(step) = self.steps.iter().find(|s| !s.in_transaction(dialect)) {
+            let (_, name, _) = step.event();
+
+            return Err(eyre!(
+                "cannot run the plan atomically, {name} runs outside a transaction"
+            ));
+        }
+
+        db.begin()?;
+
+        for step in &self.steps {
+            let (event, name, hash) = step.event();
+            let start = Instant::now();
+            let result = step.execute(db, options, &self.timeouts);
+            let entry = HistoryEntry::new(event, &name, Some(hash), &result, elapsed_ms(start));
+
+            if let Err(err) = result {
+                db.rollback()?;
+
+                // Only the failure remains of the plan, outside the rolled back transaction
+                if let Err(record_err) = db.record_history(&entry) {
+                    warn!("unable to record {event} of {name} in the history: {record_err}");
+                }
+
+                warn!("rolled back the whole plan");
+
+                return Err(self.explain_timeout(step, err, 1));
+            }
+
+            db.record_history(&entry)?;
+        }
+
+        db.commit()?;
+
+        for step in &self.steps {
+            println!("{step}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Run `op` with the given timeouts, restoring the defaults after.
+fn with_timeouts(
+    db: &mut Box<dyn DatabaseAdapter>,
+    statement: Option<Duration>,
+    lock: Option<Duration>,
+    op: impl FnOnce(&mut Box<dyn DatabaseAdapter>) -> Result<()>,
+) -> Result<()> {
+    if statement.is_none() && lock.is_none() {
+        return op(db);
+    }
+
+    db.set_timeouts(statement, lock)?;
+
+    let result = op(db);
+    let reset = db.set_timeouts(None, None);
+
+    result.and(reset)
+}
+
+/// The timeout that cancelled a migration, if any.
+fn timeout_of(err: &eyre::Report) -> Option<Timeout> {
+    err.downcast_ref::<MigrationError>().and_then(|e| e.timeout)
+}
+
+impl Plan {
+    /// Back up the database to `--backup-dir`, if set, before running a plan
+    /// with steps that a failure wouldn't roll back.
+    pub fn backup(
+        mut self,
+        db: &mut Box<dyn DatabaseAdapter>,
+        opts: &Options,
+        options: &PlanOptions,
+    ) -> Result<Self> {
+        let Some(dir) = &opts.backup_dir else {
+            return Ok(self);
+        };
+
+        if options.plan_only || options.script.is_some() {
+            return Ok(self);
+        }
+
+        let dialect = db.dialect();
+
+        if self.steps.iter().any(|s| s.needs_backup(dialect)) {
+            self.backup = Some(backup::create(db, opts.get_url()?, dir)?);
+        }
+
+        Ok(self)
+    }
+
+    /// Ask for confirmation before running a plan that rolls back migrations or syncs
+    /// a rollup over the tracking table of a protected database, see [`protect::confirm`].
+    pub fn confirm(self, opts: &Options, options: &PlanOptions) -> Result<Self> {
+        if options.plan_only || options.script.is_some() || !opts.protected || opts.yes {
+            return Ok(self);
+        }
+
+        let mut actions = Vec::new();
+
+        if let Some(PlanStep::Sync(m)) = self.steps.iter().find(|s| matches!(s, PlanStep::Sync(_)))
+        {
+            actions.push(format!(
+                "replace the recorded migrations with rollup {}",
+                m.compound_name
+            ));
+        }
+
+        let downs = self
+            .steps
+            .iter()
+            .filter(|s| matches!(s, PlanStep::Down(_)))
+            .count();
+
+        if downs > 0 {
+            actions.push(format!("roll back {downs} migration(s)"));
+        }
+
+        if actions.is_empty() {
+            return Ok(self);
+        }
+
+        eprint!("{self}");
+
+        protect::confirm(opts, &actions.join(" and "))?;
+
+        Ok(self)
+    }
+
+    /// Render the plan as a SQL script that has the same effect as running it.
+    pub fn render(&self, db: &dyn DatabaseAdapter, options: &PlanOptions) -> String {
+        let mut out = format!("-- Generated by crude {}\n", env!("CARGO_PKG_VERSION"));
+
+        for step in &self.steps {
+            let section = match step.clone() {
+                PlanStep::Down(m) => {
+                    format!(
+                        "-- Down - {}\n\n{}",
+                        m.compound_name,
+                        db.render_down_migration(&m)
+                    )
+                }
+                PlanStep::Up(mut m) => {
+                    if !options.seed {
+                        m.seed_sql = None;
+                    }
+
+                    format!(
+                        "-- Up - {}\n\n{}",
+                        m.compound_name,
+                        db.render_up_migration(&m)
+                    )
+                }
+                PlanStep::Repeat(r) => {
+                    format!("-- Repeat - {}\n\n{}", r.label(), db.render_repeatable(&r))
+                }
+                PlanStep::Sync(m) => {
+                    format!(
+                        "-- Sync - {}\n\n{}",
+                        m.compound_name,
+                        db.render_sync_rollup(&m)
+                    )
+                }
+            };
+
+            out.push('\n');
+            out.push_str(&section);
+        }
+
+        out
+    }
 }
 
 /// Builder for migration plans (`up`, `down`, `redo`, `fix`, `status`).
@@ -106,29 +619,51 @@ pub struct Planner {
     local: Vec<Migration>,
     remote: Vec<Migration>,
     count: Option<usize>,
+    target: Option<String>,
     ignore_divergent: bool,
     ignore_unreversible: bool,
     local_map: HashMap<String, Migration>,
     remote_map: HashMap<String, Migration>,
+    local_repeatables: Vec<Repeatable>,
+    remote_repeatables: Vec<Repeatable>,
+    vars: Variables,
+    timeouts: Timeouts,
 }
 
 impl Planner {
-    /// Start a new plan builder.
-    pub fn new(opts: &Options) -> Result<Self> {
+    /// Start a new plan builder, reading applied migrations through `db`.
+    pub fn new(opts: &Options, db: &mut Box<dyn DatabaseAdapter>) -> Result<Self> {
         let migrations_dir = get_migrations_dir(opts);
-        let local = migrations_dir.load()?;
-
-        let mut db = get_db_adapter(opts, false)?;
         let remote = db.load_migrations()?;
 
+        let env = opts.env.as_deref();
+        let mut local = Vec::new();
+
+        // Migrations for other environments are left out unless they were applied already
+        for mut m in migrations_dir.load()? {
+            if !m.meta.applies_to(env) && !remote.iter().any(|r| r.compound_name == m.compound_name)
+            {
+                debug!("skipping {} outside of its environments", m.compound_name);
+                continue;
+            }
+
+            m.set_environment(env)?;
+            local.push(m);
+        }
+
         let planner = Self {
             local: Vec::new(),
             remote: Vec::new(),
             count: Some(1),
+            target: None,
             ignore_divergent: false,
             ignore_unreversible: false,
             local_map: HashMap::new(),
             remote_map: HashMap::new(),
+            local_repeatables: migrations_dir.load_repeatables()?,
+            remote_repeatables: db.load_repeatables()?,
+            vars: Variables::from_options(opts)?,
+            timeouts: Timeouts::from_options(opts),
         }
         .local_migrations(&local)
         .remote_migrations(&remote);
@@ -161,6 +696,12 @@ impl Planner {
         self
     }
 
+    /// Plan up to (and including) a migration instead of a number of them.
+    pub fn target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
+
     pub fn set_ignore_divergent(mut self, ignore_divergent: bool) -> Self {
         self.ignore_divergent = ignore_divergent;
         self
@@ -195,6 +736,9 @@ impl Planner {
                     Status {
                         state,
                         migration: local.clone(),
+                        local_hash: Some(local.hash.clone()),
+                        remote_hash: Some(remote.hash.clone()),
+                        applied: remote.applied.clone(),
                     }
                 } else if local.compound_name < remote.compound_name {
                     let migration = local.clone();
@@ -203,6 +747,9 @@ impl Planner {
 
                     Status {
                         state: MigrationState::Pending,
+                        local_hash: Some(migration.hash.clone()),
+                        remote_hash: None,
+                        applied: None,
                         migration,
                     }
                 } else {
@@ -212,6 +759,9 @@ impl Planner {
 
                     Status {
                         state: MigrationState::Divergent,
+                        local_hash: None,
+                        remote_hash: Some(migration.hash.clone()),
+                        applied: migration.applied.clone(),
                         migration,
                     }
                 }
@@ -222,6 +772,9 @@ impl Planner {
 
                 Status {
                     state: MigrationState::Pending,
+                    local_hash: Some(migration.hash.clone()),
+                    remote_hash: None,
+                    applied: None,
                     migration,
                 }
             } else {
@@ -231,6 +784,9 @@ impl Planner {
 
                 Status {
                     state: MigrationState::Divergent,
+                    local_hash: None,
+                    remote_hash: Some(migration.hash.clone()),
+                    applied: migration.applied.clone(),
                     migration,
                 }
             };
@@ -241,6 +797,146 @@ impl Planner {
         Ok(res)
     }
 
+    /// Status of the migrations that are not applied.
+    ///
+    /// Applied migrations replaced by a pending rollup are left out, as `up` syncs the
+    /// rollup over them instead of reporting them divergent.
+    pub fn unapplied_status(&self) -> Result<Vec<Status>> {
+        let rollup = self.needs_rollup_sync();
+
+        Ok(self
+            .status()?
+            .into_iter()
+            .filter(|s| s.state != MigrationState::Applied)
+            .filter(|s| !(rollup && s.state == MigrationState::Divergent))
+            .collect())
+    }
+
+    /// Build a plan from its steps, substituting variables in the SQL they run.
+    fn plan(&self, mut steps: Vec<PlanStep>) -> Result<Plan> {
+        for step in &mut steps {
+            match step {
+                PlanStep::Up(m) | PlanStep::Down(m) => m.substitute(&self.vars)?,
+                PlanStep::Repeat(r) => r.substitute(&self.vars)?,
+                PlanStep::Sync(_) => {}
+            }
+        }
+
+        Ok(Plan {
+            steps,
+            backup: None,
+            timeouts: self.timeouts,
+        })
+    }
+
+    /// Complete an applied migration with the down SQL and metadata of its local files.
+    fn with_local_files(&self, mut m: Migration) -> Migration {
+        if let Some(local) = self.local_map.get(&m.compound_name) {
+            if local.down_sql.is_some() {
+                m.down_sql = local.down_sql.clone();
+                m.path = local.path.clone();
+                m.sections = local.sections.clone();
+            }
+
+            m.meta = local.meta.clone();
+        }
+
+        m
+    }
+
+    /// Build status listing for each repeatable migration, sorted by name.
+    pub fn repeatable_status(&self) -> Vec<RepeatableStatus> {
+        let mut names = self
+            .local_repeatables
+            .iter()
+            .chain(&self.remote_repeatables)
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names.dedup();
+
+        names
+            .into_iter()
+            .map(|name| {
+                let local = self.local_repeatables.iter().find(|r| r.name == name);
+                let remote = self.remote_repeatables.iter().find(|r| r.name == name);
+
+                let state = match (local, remote) {
+                    (Some(_), None) => RepeatableState::Pending,
+                    (Some(l), Some(r)) if l.hash != r.hash => RepeatableState::Changed,
+                    (Some(_), Some(_)) => RepeatableState::Applied,
+                    (None, _) => RepeatableState::Removed,
+                };
+
+                RepeatableStatus {
+                    state,
+                    repeatable: local.or(remote).unwrap().clone(),
+                    local_hash: local.map(|r| r.hash.clone()),
+                    remote_hash: remote.map(|r| r.hash.clone()),
+                    applied: remote.and_then(|r| r.applied.clone()),
+                }
+            })
+            .collect()
+    }
+
+    /// Steps (re-)applying new and changed repeatable migrations.
+    fn repeat_steps(&self) -> Vec<PlanStep> {
+        self.repeatable_status()
+            .into_iter()
+            .filter(|s| matches!(s.state, RepeatableState::Pending | RepeatableState::Changed))
+            .map(|s| PlanStep::Repeat(s.repeatable))
+            .collect()
+    }
+
+    /// Resolve the target to the compound name of an applied or pending migration.
+    ///
+    /// The target is either a compound name, a short name or a prefix of the compound name
+    /// (usually the timestamp).
+    fn resolve_target(&self, target: &str) -> Result<String> {
+        let find = |migrations: &[Migration]| -> Result<Option<String>> {
+            if let Some(m) = migrations.iter().find(|m| m.compound_name == target) {
+                return Ok(Some(m.compound_name.clone()));
+            }
+
+            let matches = migrations
+                .iter()
+                .filter(|m| m.name == target || m.compound_name.starts_with(target))
+                .collect::<Vec<_>>();
+
+            match matches.as_slice() {
+                [] => Ok(None),
+                [m] => Ok(Some(m.compound_name.clone())),
+                _ => Err(eyre!(
+                    "target {target} is ambiguous, it matches {}",
+                    matches
+                        .iter()
+                        .map(|m| m.compound_name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            }
+        };
+
+        match (find(&self.local)?, find(&self.remote)?) {
+            (Some(name), _) => Ok(name),
+            (None, Some(name)) => Err(eyre!("target migration {name} is divergent")),
+            (None, None) => Err(eyre!("unable to find target migration {target}")),
+        }
+    }
+
+    /// Number of applied migrations (oldest first) to undo so that `target` is the last one left.
+    fn count_after_target(&self, applied: &[Migration], target: &str) -> Result<usize> {
+        let name = self.resolve_target(target)?;
+
+        let position = applied
+            .iter()
+            .position(|m| m.compound_name == name)
+            .ok_or_else(|| eyre!("target migration {name} is not applied"))?;
+
+        Ok(applied.len() - position - 1)
+    }
+
     fn check_rollup(&self) -> Result<()> {
         if self
             .local
@@ -255,68 +951,117 @@ impl Planner {
         Ok(())
     }
 
-    fn sync_rollup(mut self, db: &mut Box<dyn DatabaseAdapter>) -> Result<Self> {
-        // Is there a pending rollup migration?
-        if let Some(rollup) = self
-            .local
+    /// The local rollup migration that has not been recorded in the database yet.
+    fn pending_rollup(&self) -> Option<&Migration> {
+        self.local
             .iter()
             .find(|m| m.name == "rollup" && !self.remote_map.contains_key(&m.compound_name))
-        {
-            // If there's any pending migrations before the rollup, error out
-            if self.local.iter().any(|m| {
-                m.name != "init"
-                    && m.name != "rollup"
-                    && !self.remote_map.contains_key(&m.compound_name)
-                    && m.compound_name < rollup.compound_name
-            }) {
-                return Err(eyre!(
-                    "pending migrations before the rollup, please re-order them to the end"
-                ));
-            }
-
-            // If there are any remote non-divergent migrations, error out
-            if !self
-                .remote
-                .iter()
-                .filter(|m| m.name != "init")
-                .all(|m| !self.local_map.contains_key(&m.compound_name))
-            {
-                return Err(eyre!(
-                    "unable to sync the rollup, please reset the database"
-                ));
-            }
+    }
 
-            // Sync the rollup only if it's not during startup of database
-            if !self.remote.is_empty() {
-                db.clear_migrations()?;
-                db.record_baseline(&rollup.compound_name, &rollup.hash)?;
+    /// Whether `up` would sync a rollup into an already migrated database.
+    pub fn needs_rollup_sync(&self) -> bool {
+        self.pending_rollup()
+            .is_some_and(|rollup| !self.remote.is_empty() && self.can_sync(rollup).is_ok())
+    }
 
-                println!("{} - {}", "Sync".cyan(), rollup.compound_name);
+    /// Error out unless the database can be reset to the `rollup` baseline.
+    fn can_sync(&self, rollup: &Migration) -> Result<()> {
+        // If there's any pending migrations before the rollup, error out
+        if self.local.iter().any(|m| {
+            m.name != "init"
+                && m.name != "rollup"
+                && !self.remote_map.contains_key(&m.compound_name)
+                && m.compound_name < rollup.compound_name
+        }) {
+            return Err(eyre!(
+                "pending migrations before the rollup, please re-order them to the end"
+            ));
+        }
 
-                self = self.remote_migrations(&db.load_migrations()?);
-            }
+        // If there are any remote non-divergent migrations, error out
+        if !self
+            .remote
+            .iter()
+            .filter(|m| m.name != "init")
+            .all(|m| !self.local_map.contains_key(&m.compound_name))
+        {
+            return Err(eyre!(
+                "unable to sync the rollup, please reset the database"
+            ));
         }
 
-        Ok(self)
+        Ok(())
+    }
+
+    /// Step syncing a pending rollup over the migrations applied to the database, if any.
+    fn sync_step(&self) -> Result<Option<PlanStep>> {
+        // Is there a pending rollup migration?
+        let Some(rollup) = self.pending_rollup() else {
+            return Ok(None);
+        };
+
+        self.can_sync(rollup)?;
+
+        // Sync the rollup only if it's not during startup of database
+        Ok((!self.remote.is_empty()).then(|| PlanStep::Sync(rollup.clone())))
     }
 
     /// Plan applying migrations (`up`).
-    pub fn up(mut self, db: &mut Box<dyn DatabaseAdapter>) -> Result<Plan> {
-        self = self.sync_rollup(db)?;
+    pub fn up(mut self) -> Result<Plan> {
+        let sync = self.sync_step()?;
+
+        // The rest is planned against the tracking table as the sync leaves it
+        if let Some(PlanStep::Sync(rollup)) = &sync {
+            let synced = [self.remote[0].clone(), rollup.clone()];
+
+            self = self.remote_migrations(&synced);
+        }
+
+        let target = self
+            .target
+            .as_deref()
+            .map(|target| self.resolve_target(target))
+            .transpose()?;
+
+        if let Some(name) = &target
+            && self.remote_map.contains_key(name)
+        {
+            return Err(eyre!("target migration {name} is already applied"));
+        }
 
         let pending = self
             .local
             .iter()
             .filter(|m| !self.remote_map.contains_key(&m.compound_name))
+            .filter(|m| target.as_ref().is_none_or(|name| &m.compound_name <= name))
             .cloned()
             .collect::<Vec<_>>();
 
-        let to_do = self.count.unwrap_or(pending.len());
+        let to_do = if target.is_some() {
+            pending.len()
+        } else {
+            self.count.unwrap_or(pending.len())
+        };
         let take = min(to_do, pending.len());
 
-        let steps = pending.into_iter().take(take).map(PlanStep::Up).collect();
+        // Repeatables run after all versioned migrations, not after some of them
+        let remaining = self
+            .local
+            .iter()
+            .filter(|m| !self.remote_map.contains_key(&m.compound_name))
+            .count()
+            - take;
+
+        let mut steps = sync
+            .into_iter()
+            .chain(pending.into_iter().take(take).map(PlanStep::Up))
+            .collect::<Vec<_>>();
+
+        if remaining == 0 {
+            steps.extend(self.repeat_steps());
+        }
 
-        Ok(Plan { steps })
+        self.plan(steps)
     }
 
     /// Plan rolling back migrations (`down`).
@@ -328,33 +1073,26 @@ impl Planner {
             .iter()
             .filter(|m| !self.ignore_divergent || self.local_map.contains_key(&m.compound_name))
             .cloned()
-            .map(|mut m| {
-                if let Some(local) = self.local_map.get(&m.compound_name) {
-                    m.down_sql = local.down_sql.clone().or(m.down_sql);
-                }
-
-                m
-            })
-            .rev()
+            .map(|m| self.with_local_files(m))
             .collect::<Vec<_>>();
 
-        let to_rollback = self.count.unwrap_or(applied.len());
+        let to_rollback = match &self.target {
+            Some(target) => self.count_after_target(&applied, target)?,
+            None => self.count.unwrap_or(applied.len()),
+        };
+
+        let applied = applied.into_iter().rev().collect::<Vec<_>>();
         let take = min(to_rollback, applied.len());
 
         let applied = applied.into_iter().take(take).collect::<Vec<_>>();
 
         for m in &applied {
-            if !self.ignore_unreversible && m.down_sql.is_none() {
-                return Err(eyre!(
-                    "unable to rollback unreversible migration {}",
-                    m.compound_name
-                ));
-            }
+            check_reversible(m, "rollback", self.ignore_unreversible)?;
         }
 
         let steps = applied.into_iter().map(PlanStep::Down).collect();
 
-        Ok(Plan { steps })
+        self.plan(steps)
     }
 
     /// Plan redoing the most recent migrations (`redo`).
@@ -370,26 +1108,18 @@ impl Planner {
             .filter(|m| m.name != "init" && m.name != "rollup")
             .filter(|m| !self.ignore_divergent || self.local_map.contains_key(&m.compound_name))
             .cloned()
-            .map(|mut m| {
-                if let Some(local) = self.local_map.get(&m.compound_name) {
-                    m.down_sql = local.down_sql.clone().or(m.down_sql);
-                }
-
-                m
-            })
+            .map(|m| self.with_local_files(m))
             .collect::<Vec<_>>();
 
-        let count = self.count.unwrap_or(applied.len());
+        let count = match &self.target {
+            Some(target) => self.count_after_target(&applied, target)? + 1,
+            None => self.count.unwrap_or(applied.len()),
+        };
 
         let recent = applied.into_iter().rev().take(count).collect::<Vec<_>>();
 
         for m in recent {
-            if m.down_sql.is_none() {
-                return Err(eyre!(
-                    "unable to redo unreversible migration {}",
-                    m.compound_name
-                ));
-            }
+            check_reversible(&m, "redo", false)?;
 
             if let Some(local) = self.local_map.get(&m.compound_name) {
                 down_steps.push(PlanStep::Down(m.clone()));
@@ -402,12 +1132,12 @@ impl Planner {
             }
         }
 
-        Ok(Plan {
-            steps: down_steps
+        self.plan(
+            down_steps
                 .into_iter()
                 .chain(up_steps.into_iter().rev())
                 .collect(),
-        })
+        )
     }
 
     /// Plan fixing variant/divergent migrations and applying pending ones (`fix`).
@@ -433,18 +1163,9 @@ impl Planner {
 
         // Get all db migrations from the index onwards
         for orig_m in self.remote.iter().skip(index).rev() {
-            let mut m = orig_m.clone();
-
-            if let Some(local) = self.local_map.get(&m.compound_name) {
-                m.down_sql = local.down_sql.clone().or(m.down_sql);
-            }
+            let m = self.with_local_files(orig_m.clone());
 
-            if m.down_sql.is_none() {
-                return Err(eyre!(
-                    "unable to rollback unreversible migration {}",
-                    m.compound_name
-                ));
-            }
+            check_reversible(&m, "rollback", false)?;
 
             steps.push(PlanStep::Down(m));
         }
@@ -460,12 +1181,52 @@ impl Planner {
             steps.push(PlanStep::Up(m.clone()));
         }
 
-        Ok(Plan { steps })
+        steps.extend(self.repeat_steps());
+
+        self.plan(steps)
     }
 }
 
-/// Print the status of each migration (Applied, Pending, Variant, Divergent).
-pub fn print_status(statuses: &[Status]) {
+/// Ensure an applied migration can be rolled back, `action` names the operation in errors.
+fn check_reversible(m: &Migration, action: &str, ignore_unreversible: bool) -> Result<()> {
+    if m.meta.irreversible {
+        return Err(eyre!(
+            "unable to {action} migration {}, it is marked irreversible in its {METADATA_FILE}",
+            m.compound_name
+        ));
+    }
+
+    if !ignore_unreversible && m.down_sql.is_none() {
+        return Err(eyre!(
+            "unable to {action} unreversible migration {}",
+            m.compound_name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Print the status of each migration (Applied, Pending, Variant, Divergent),
+/// followed by repeatable migrations (Applied, Pending, Changed, Removed).
+///
+/// With `long`, text output also shows when, where and how each migration was applied,
+/// and the description, author and tags from its `migration.toml`.
+pub fn print_status(
+    statuses: &[Status],
+    repeatables: &[RepeatableStatus],
+    format: OutputFormat,
+    long: bool,
+) -> Result<()> {
+    if format != OutputFormat::Text {
+        let records = statuses
+            .iter()
+            .map(StatusRecord::from)
+            .chain(repeatables.iter().map(StatusRecord::from))
+            .collect::<Vec<_>>();
+
+        return print_records(&records, format);
+    }
+
     for status in statuses.iter() {
         let label = match status.state {
             MigrationState::Applied => format!("{:>9}", "Applied".green()),
@@ -474,6 +1235,278 @@ pub fn print_status(statuses: &[Status]) {
             MigrationState::Divergent => format!("{:>9}", "Divergent".red()),
         };
 
-        println!("{label:<9} - {}", status.migration.compound_name);
+        print_status_line(
+            &label,
+            &status.migration.compound_name,
+            status.applied.as_ref().filter(|_| long),
+        );
+
+        let about = status.migration.meta.describe();
+
+        if long && !about.is_empty() {
+            println!("{:12}{}", "", about.italic());
+        }
+    }
+
+    for status in repeatables.iter() {
+        let label = match status.state {
+            RepeatableState::Applied => format!("{:>9}", "Applied".green()),
+            RepeatableState::Pending => format!("{:>9}", "Pending".yellow()),
+            RepeatableState::Changed => format!("{:>9}", "Changed".yellow()),
+            RepeatableState::Removed => format!("{:>9}", "Removed".red()),
+        };
+
+        print_status_line(
+            &label,
+            &status.repeatable.label(),
+            status.applied.as_ref().filter(|_| long),
+        );
+    }
+
+    Ok(())
+}
+
+fn print_status_line(label: &str, name: &str, applied: Option<&Applied>) {
+    match applied {
+        Some(applied) => println!("{label:<9} - {name} {}", describe_applied(applied).dimmed()),
+        None => println!("{label:<9} - {name}"),
+    }
+}
+
+/// Summarize execution details, skipping those missing from older tracking tables.
+fn describe_applied(applied: &Applied) -> String {
+    let mut parts = Vec::new();
+
+    if let Some(applied_at) = applied.applied_at {
+        parts.push(format!("at {applied_at}"));
+    }
+
+    match (&applied.applied_by, &applied.hostname) {
+        (Some(user), Some(host)) => parts.push(format!("by {user}@{host}")),
+        (Some(user), None) => parts.push(format!("by {user}")),
+        _ => {}
+    }
+
+    if let Some(duration_ms) = applied.duration_ms {
+        parts.push(format!("in {duration_ms}ms"));
+    }
+
+    if let Some(version) = &applied.crude_version {
+        parts.push(format!("with crude {version}"));
+    }
+
+    if applied.seeded == Some(true) {
+        parts.push(String::from("seeded"));
+    }
+
+    if let Some(environment) = &applied.environment {
+        parts.push(format!("in {environment} environment"));
+    }
+
+    if let Some(updated_at) = applied
+        .updated_at
+        .filter(|u| Some(*u) != applied.applied_at)
+    {
+        parts.push(format!("updated at {updated_at}"));
+    }
+
+    format!("({})", parts.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrations(names: &[&str]) -> Vec<Migration> {
+        names
+            .iter()
+            .map(|n| {
+                Migration::from_db(n.to_string(), String::from("hash"), Some(String::new()))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn planner(local: &[&str], remote: &[&str]) -> Planner {
+        Planner {
+            local: Vec::new(),
+            remote: Vec::new(),
+            count: None,
+            target: None,
+            ignore_divergent: false,
+            ignore_unreversible: false,
+            local_map: HashMap::new(),
+            remote_map: HashMap::new(),
+            local_repeatables: Vec::new(),
+            remote_repeatables: Vec::new(),
+            vars: Variables::default(),
+            timeouts: Timeouts::default(),
+        }
+        .local_migrations(&migrations(local))
+        .remote_migrations(&migrations(remote))
+    }
+
+    fn names(plan: &Plan) -> Vec<String> {
+        plan.steps
+            .iter()
+            .map(|step| match step {
+                PlanStep::Up(m) | PlanStep::Down(m) | PlanStep::Sync(m) => m.compound_name.clone(),
+                PlanStep::Repeat(r) => r.label(),
+            })
+            .collect()
+    }
+
+    const ALL: [&str; 4] = [
+        "20000101000000_init",
+        "20230101000000_a",
+        "20230201000000_b",
+        "20230301000000_c",
+    ];
+
+    #[test]
+    fn test_down_to_target() {
+        let plan = planner(&ALL, &ALL)
+            .target(Some(String::from("20230101")))
+            .down()
+            .unwrap();
+
+        assert_eq!(names(&plan), vec!["20230301000000_c", "20230201000000_b"]);
+    }
+
+    #[test]
+    fn test_redo_to_target() {
+        let plan = planner(&ALL, &ALL)
+            .target(Some(String::from("b")))
+            .redo()
+            .unwrap();
+
+        assert_eq!(
+            names(&plan),
+            vec![
+                "20230301000000_c",
+                "20230201000000_b",
+                "20230201000000_b",
+                "20230301000000_c"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rollup_status() {
+        let rolled_up = planner(&["20000101000000_init", "20230401000000_rollup"], &ALL);
+
+        assert!(rolled_up.needs_rollup_sync());
+        assert_eq!(
+            rolled_up
+                .unapplied_status()
+                .unwrap()
+                .iter()
+                .map(|s| (s.state.clone(), s.migration.compound_name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(MigrationState::Pending, "20230401000000_rollup")]
+        );
+
+        // Without a rollup to sync, migrations missing locally are divergent
+        let divergent = planner(&["20000101000000_init"], &ALL);
+
+        assert!(!divergent.needs_rollup_sync());
+        assert!(
+            divergent
+                .unapplied_status()
+                .unwrap()
+                .iter()
+                .all(|s| s.state == MigrationState::Divergent)
+        );
+    }
+
+    #[test]
+    fn test_up_syncs_rollup() {
+        let plan = planner(
+            &[
+                "20000101000000_init",
+                "20230401000000_rollup",
+                "20230501000000_d",
+            ],
+            &ALL,
+        )
+        .count(Some(1))
+        .up()
+        .unwrap();
+
+        assert!(matches!(plan.steps[0], PlanStep::Sync(_)));
+        assert_eq!(
+            names(&plan),
+            vec!["20230401000000_rollup", "20230501000000_d"]
+        );
+    }
+
+    #[test]
+    fn test_target_errors() {
+        let err = planner(&ALL, &ALL)
+            .target(Some(String::from("2023")))
+            .down()
+            .unwrap_err();
+        assert!(err.to_string().contains("ambiguous"));
+
+        let err = planner(&ALL[..2], &ALL)
+            .target(Some(String::from("c")))
+            .down()
+            .unwrap_err();
+        assert!(err.to_string().contains("divergent"));
+
+        let err = planner(&ALL, &ALL[..2])
+            .target(Some(String::from("20230201000000_b")))
+            .down()
+            .unwrap_err();
+        assert!(err.to_string().contains("not applied"));
+
+        let err = planner(&ALL, &ALL)
+            .target(Some(String::from("x")))
+            .redo()
+            .unwrap_err();
+        assert!(err.to_string().contains("unable to find"));
+    }
+
+    #[test]
+    fn test_repeatable_status() {
+        let repeatable = |name: &str, hash: &str| {
+            Repeatable::from_db(name.to_string(), hash.to_string(), Applied::default())
+        };
+
+        let mut planner = planner(&ALL, &ALL);
+        planner.local_repeatables = vec![
+            repeatable("a", "1"),
+            repeatable("b", "2"),
+            repeatable("c", "3"),
+        ];
+        planner.remote_repeatables = vec![
+            repeatable("b", "2"),
+            repeatable("c", "0"),
+            repeatable("d", "4"),
+        ];
+
+        let states = planner
+            .repeatable_status()
+            .into_iter()
+            .map(|s| (s.repeatable.name, s.state))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            states,
+            vec![
+                (String::from("a"), RepeatableState::Pending),
+                (String::from("b"), RepeatableState::Applied),
+                (String::from("c"), RepeatableState::Changed),
+                (String::from("d"), RepeatableState::Removed),
+            ]
+        );
+
+        let plan = Plan {
+            steps: planner.repeat_steps(),
+            backup: None,
+            timeouts: Timeouts::default(),
+        };
+
+        assert_eq!(names(&plan), vec!["repeatable/a", "repeatable/c"]);
     }
 }
@@ -0,0 +1,135 @@
+use std::{collections::BTreeMap, env::vars, fs::read_to_string};
+
+use eyre::eyre;
+use regex::{Captures, Regex};
+
+use crate::{
+    Options,
+    error::Result,
+    migration::Source,
+    sql::{location, snippet},
+};
+
+/// Prefix of environment variables defining substitution variables.
+const ENV_PREFIX: &str = "CRUDE_VAR_";
+
+/// Values substituted for `${name}` placeholders in migration SQL.
+///
+/// Substitution happens after hashing, so a migration keeps the same hash in every
+/// environment regardless of the values it is applied with. It is opt-in: without any
+/// variable defined, SQL is left untouched, `$${name}` escapes included, so that a
+/// literal `${...}` in older migrations keeps applying as written.
+#[derive(Debug, Clone, Default)]
+pub struct Variables(BTreeMap<String, String>);
+
+impl Variables {
+    /// Collect variables from the `--var-file`, then `CRUDE_VAR_<NAME>` environment
+    /// variables (with lowercased names), then `--var` flags, later ones taking precedence.
+    pub fn from_options(opts: &Options) -> Result<Self> {
+        let mut values = BTreeMap::new();
+
+        if let Some(path) = &opts.var_file {
+            let contents =
+                read_to_string(path).map_err(|e| eyre!("unable to read {}: {}", path, e))?;
+
+            let file: BTreeMap<String, String> =
+                toml::from_str(&contents).map_err(|e| eyre!("invalid {}: {}", path, e))?;
+
+            values.extend(file);
+        }
+
+        for (key, value) in vars() {
+            if let Some(name) = key.strip_prefix(ENV_PREFIX) {
+                values.insert(name.to_lowercase(), value);
+            }
+        }
+
+        for var in &opts.vars {
+            let (name, value) = var
+                .split_once('=')
+                .ok_or_else(|| eyre!("invalid variable {var}, expected NAME=VALUE"))?;
+
+            values.insert(name.to_string(), value.to_string());
+        }
+
+        Ok(Variables(values))
+    }
+
+    /// Replace the placeholders in `sql`, erroring on undefined variables.
+    pub fn substitute(&self, name: &str, source: &Source, sql: &str) -> Result<String> {
+        if self.0.is_empty() || !sql.contains("${") {
+            return Ok(sql.to_string());
+        }
+
+        // `${name}` placeholders, `$${name}` escapes them
+        let placeholder = Regex::new(r"\$(\$)?\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+
+        if let Some(missing) = placeholder
+            .captures_iter(sql)
+            .find(|c| c.get(1).is_none() && !self.0.contains_key(&c[2]))
+        {
+            let offset = missing.get(0).unwrap().start();
+            let (line, column) = location(sql, offset);
+            let line = line + source.line_offset;
+
+            return Err(eyre!(
+                "migration {name} uses undefined variable {} at {source}:{line}:{column}\n{}\n\
+                define it with `--var {}=VALUE`, {ENV_PREFIX}{} or in the --var-file",
+                &missing[0],
+                snippet(sql, offset, source.line_offset),
+                &missing[2],
+                missing[2].to_uppercase(),
+            ));
+        }
+
+        Ok(placeholder
+            .replace_all(sql, |c: &Captures| match c.get(1) {
+                Some(_) => format!("${{{}}}", &c[2]),
+                None => self.0[&c[2]].clone(),
+            })
+            .into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let vars = Variables(BTreeMap::from([
+            (String::from("schema"), String::from("app")),
+            (String::from("role"), String::from("app_rw")),
+        ]));
+        let source = Source {
+            path: String::from("up.sql"),
+            line_offset: 0,
+        };
+
+        assert_eq!(
+            vars.substitute("m", &source, "GRANT ALL ON ${schema}.t TO ${role};")
+                .unwrap(),
+            "GRANT ALL ON app.t TO app_rw;"
+        );
+        assert_eq!(
+            vars.substitute("m", &source, "SELECT '$${schema}', $1, $$x$$;")
+                .unwrap(),
+            "SELECT '${schema}', $1, $$x$$;"
+        );
+
+        let err = vars
+            .substitute("m", &source, "SELECT 1;\nSELECT ${nope};")
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("migration m uses undefined variable ${nope} at up.sql:2:8")
+        );
+
+        assert_eq!(
+            Variables::default()
+                .substitute("m", &source, "SELECT '${nope}', '$${nope}';")
+                .unwrap(),
+            "SELECT '${nope}', '$${nope}';"
+        );
+    }
+}
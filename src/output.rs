@@ -0,0 +1,145 @@
+use anstream::{print, println};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::error::Result;
+
+/// Format for listings meant to be read by other programs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Coloured text for humans
+    #[default]
+    Text,
+    /// A JSON array of objects
+    Json,
+    /// A YAML sequence of mappings
+    Yaml,
+    /// Tab separated values with a header row
+    Tsv,
+}
+
+/// Print serializable records in a machine-readable format.
+///
+/// Records must serialize to flat objects, nested values are printed as JSON.
+pub fn print_records<T: Serialize>(records: &[T], format: OutputFormat) -> Result<()> {
+    let records = records
+        .iter()
+        .map(|r| match serde_json::to_value(r)? {
+            Value::Object(map) => Ok(map),
+            _ => Err(eyre::eyre!("records must serialize to objects")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    match format {
+        OutputFormat::Text | OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&records)?);
+        }
+        OutputFormat::Yaml => print!("{}", to_yaml(&records)),
+        OutputFormat::Tsv => print!("{}", to_tsv(&records)),
+    }
+
+    Ok(())
+}
+
+/// Render records as YAML, JSON scalars are valid YAML flow scalars.
+fn to_yaml(records: &[Map<String, Value>]) -> String {
+    if records.is_empty() {
+        return String::from("[]\n");
+    }
+
+    let mut out = String::new();
+
+    for record in records {
+        for (i, (key, value)) in record.iter().enumerate() {
+            let prefix = if i == 0 { "- " } else { "  " };
+
+            out.push_str(&format!("{prefix}{key}: {value}\n"));
+        }
+    }
+
+    out
+}
+
+/// Render records as TSV, escaping characters that would break the layout.
+fn to_tsv(records: &[Map<String, Value>]) -> String {
+    let Some(first) = records.first() else {
+        return String::new();
+    };
+
+    let mut out = first.keys().cloned().collect::<Vec<_>>().join("\t") + "\n";
+
+    for record in records {
+        let row = record
+            .values()
+            .map(|value| {
+                let cell = match value {
+                    Value::Null => String::new(),
+                    Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                };
+
+                cell.replace('\\', "\\\\")
+                    .replace('\t', "\\t")
+                    .replace('\n', "\\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\t");
+
+        out.push_str(&row);
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Record {
+        name: &'static str,
+        hash: Option<&'static str>,
+        flag: bool,
+    }
+
+    const RECORDS: [Record; 2] = [
+        Record {
+            name: "a\tb",
+            hash: Some("h"),
+            flag: true,
+        },
+        Record {
+            name: "c: d",
+            hash: None,
+            flag: false,
+        },
+    ];
+
+    fn maps() -> Vec<Map<String, Value>> {
+        RECORDS
+            .iter()
+            .map(|r| match serde_json::to_value(r).unwrap() {
+                Value::Object(map) => map,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_to_yaml() {
+        assert_eq!(
+            to_yaml(&maps()),
+            "- name: \"a\\tb\"\n  hash: \"h\"\n  flag: true\n- name: \"c: d\"\n  hash: null\n  flag: false\n"
+        );
+    }
+
+    #[test]
+    fn test_to_tsv() {
+        assert_eq!(
+            to_tsv(&maps()),
+            "name\thash\tflag\na\\tb\th\ttrue\nc: d\t\tfalse\n"
+        );
+    }
+}
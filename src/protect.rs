@@ -0,0 +1,67 @@
+use std::io::{IsTerminal, Write, stdin};
+
+use anstream::{eprint, eprintln, stderr};
+use eyre::eyre;
+use owo_colors::OwoColorize;
+
+use crate::{Options, error::Result};
+
+/// Ask for confirmation before a destructive change to a protected database.
+///
+/// The user must type the name of the database. `--yes` skips the question, and
+/// without a terminal to ask on, the change is refused unless `--yes` was given.
+pub fn confirm(opts: &Options, action: &str) -> Result<()> {
+    if !opts.protected || opts.yes {
+        return Ok(());
+    }
+
+    let name = database_name(opts.get_url()?);
+
+    if !stdin().is_terminal() {
+        return Err(eyre!(
+            "refusing to {action} on protected database {name} without a terminal, pass --yes to allow it"
+        ));
+    }
+
+    eprintln!(
+        "{} database {} is protected, about to {action}",
+        "warning:".yellow().bold(),
+        name.bold()
+    );
+    eprint!("Type {} to continue: ", name.bold());
+    stderr().flush()?;
+
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+
+    if answer.trim() != name {
+        return Err(eyre!("aborted, the database name did not match"));
+    }
+
+    Ok(())
+}
+
+/// Name of the database in a URL: the path for servers, the file name for SQLite.
+fn database_name(url: &str) -> &str {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+
+    url.rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_name() {
+        assert_eq!(database_name("postgres://app@db:5432/shop"), "shop");
+        assert_eq!(
+            database_name("mysql://root@db/shop?ssl-mode=REQUIRED"),
+            "shop"
+        );
+        assert_eq!(database_name("sqlite://db/app.db?mode=rw"), "app.db");
+        assert_eq!(database_name("sqlite::memory:"), "sqlite::memory:");
+    }
+}
@@ -0,0 +1,435 @@
+use std::fmt::Write;
+
+/// SQL dialect of a database, where it affects how scripts are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Postgres,
+    Mysql,
+    Sqlite,
+}
+
+impl Dialect {
+    /// Dialect of a database URL, by its scheme.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let scheme = url.split(':').next().unwrap_or_default();
+
+        match scheme {
+            "postgres" | "postgresql" => Some(Dialect::Postgres),
+            "mysql" | "mariadb" => Some(Dialect::Mysql),
+            "sqlite" => Some(Dialect::Sqlite),
+            _ => None,
+        }
+    }
+}
+
+/// A single statement of a SQL script, without its terminating semicolon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statement<'a> {
+    pub sql: &'a str,
+    /// Byte offset of the statement in the script.
+    pub offset: usize,
+}
+
+/// Split a script into statements on semicolons.
+///
+/// Semicolons inside string literals, quoted identifiers, comments, dollar
+/// quoted bodies and the `BEGIN ... END` bodies of routines and triggers don't
+/// end a statement. Leading comments are not part of the statement.
+pub fn split(script: &str, dialect: Dialect) -> Vec<Statement<'_>> {
+    let bytes = script.as_bytes();
+    let mut statements = Vec::new();
+
+    let mut i = 0;
+    let mut start = None;
+    let mut words = 0;
+    let mut routine = false;
+    let mut depth = 0usize;
+    let mut after_end = false;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let next = bytes.get(i + 1).copied();
+
+        match c {
+            _ if c.is_ascii_whitespace() => {
+                i += 1;
+            }
+            b'-' if next == Some(b'-') => {
+                i = skip_line(bytes, i);
+            }
+            b'#' if dialect == Dialect::Mysql => {
+                i = skip_line(bytes, i);
+            }
+            b'/' if next == Some(b'*') => {
+                i = skip_block_comment(bytes, i, dialect == Dialect::Postgres);
+            }
+            b';' if depth == 0 => {
+                if let Some(offset) = start.take() {
+                    statements.push(Statement {
+                        sql: script[offset..i].trim_end(),
+                        offset,
+                    });
+                }
+
+                words = 0;
+                routine = false;
+                after_end = false;
+                i += 1;
+            }
+            b'\'' | b'"' | b'`' => {
+                start.get_or_insert(i);
+                after_end = false;
+
+                // Postgres only treats backslashes as escapes in E'...' strings
+                let escapes = dialect == Dialect::Mysql
+                    || (c == b'\''
+                        && i > 0
+                        && bytes[i - 1].eq_ignore_ascii_case(&b'e')
+                        && (i < 2 || !is_word(bytes[i - 2])));
+
+                i = skip_quoted(bytes, i, escapes);
+            }
+            b'$' if dialect == Dialect::Postgres && !(i > 0 && is_word(bytes[i - 1])) => {
+                start.get_or_insert(i);
+                after_end = false;
+
+                i = skip_dollar_quoted(bytes, i);
+            }
+            _ if is_word(c) && !c.is_ascii_digit() => {
+                start.get_or_insert(i);
+
+                let end = i + bytes[i..].iter().take_while(|b| is_word(**b)).count();
+                let word = script[i..end].to_ascii_uppercase();
+
+                if words == 0 && word != "CREATE" {
+                    words = usize::MAX;
+                } else if words < 8 {
+                    words += 1;
+                    routine |= matches!(
+                        word.as_str(),
+                        "TRIGGER" | "FUNCTION" | "PROCEDURE" | "EVENT"
+                    );
+                }
+
+                if after_end {
+                    // `END IF` and friends close blocks that were never counted
+                    after_end = false;
+
+                    if matches!(word.as_str(), "IF" | "LOOP" | "WHILE" | "REPEAT") {
+                        depth += 1;
+                    }
+                } else if routine {
+                    match word.as_str() {
+                        "BEGIN" | "CASE" => depth += 1,
+                        "END" if depth > 0 => {
+                            depth -= 1;
+                            after_end = true;
+                        }
+                        _ => {}
+                    }
+                }
+
+                i = end;
+            }
+            _ => {
+                start.get_or_insert(i);
+
+                // Only a word right after END can be the kind of block it closes
+                after_end = false;
+                i += 1;
+            }
+        }
+    }
+
+    if let Some(offset) = start {
+        statements.push(Statement {
+            sql: script[offset..].trim_end(),
+            offset,
+        });
+    }
+
+    statements
+}
+
+/// Why a statement cannot run inside a transaction block, if it can't.
+///
+/// `ALTER TYPE ... ADD VALUE` is only restricted before Postgres 12, see [`adds_enum_value`].
+pub fn autocommit_reason(statement: &str, dialect: Dialect) -> Option<&'static str> {
+    let words = leading_words(statement);
+    let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match dialect {
+        Dialect::Postgres => match words.as_slice() {
+            ["VACUUM", ..] => Some("VACUUM"),
+            ["CREATE" | "DROP", "DATABASE", ..] => Some("CREATE/DROP DATABASE"),
+            ["CREATE" | "DROP", "TABLESPACE", ..] => Some("CREATE/DROP TABLESPACE"),
+            ["ALTER", "SYSTEM", ..] => Some("ALTER SYSTEM"),
+            ["REFRESH", ..] => None,
+            _ if words.contains(&"CONCURRENTLY") => Some("CONCURRENTLY"),
+            _ => None,
+        },
+        Dialect::Sqlite => match words.as_slice() {
+            ["VACUUM", ..] => Some("VACUUM"),
+            _ => None,
+        },
+        // DDL commits implicitly in MySQL, nothing is refused in a transaction
+        Dialect::Mysql => None,
+    }
+}
+
+/// Whether a Postgres statement adds a value to an enum type.
+pub fn adds_enum_value(statement: &str) -> bool {
+    let words = leading_words(statement);
+
+    words.starts_with(&[String::from("ALTER"), String::from("TYPE")])
+        && words.windows(2).any(|w| w[0] == "ADD" && w[1] == "VALUE")
+}
+
+/// Upper-cased leading keywords of a statement, enough to recognize its kind.
+fn leading_words(statement: &str) -> Vec<String> {
+    statement
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty())
+        .take(8)
+        .map(str::to_ascii_uppercase)
+        .collect()
+}
+
+/// 1-based line and column (in characters) of a byte offset in `script`.
+pub fn location(script: &str, offset: usize) -> (usize, usize) {
+    let before = &script[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Byte offset of the 1-based character `position` in `sql`.
+pub fn char_offset(sql: &str, position: usize) -> usize {
+    sql.char_indices()
+        .nth(position.saturating_sub(1))
+        .map_or(sql.len(), |(i, _)| i)
+}
+
+/// Show the line of `script` containing `offset`, with a caret under it.
+///
+/// Line numbers are shifted by `line_offset`, the lines preceding `script` in its file.
+pub fn snippet(script: &str, offset: usize, line_offset: usize) -> String {
+    let (line, column) = location(script, offset);
+    let text = script.lines().nth(line - 1).unwrap_or_default();
+    let line = line + line_offset;
+    let gutter = line.to_string().len();
+
+    let mut out = String::new();
+
+    writeln!(out, "{:gutter$} |", "").unwrap();
+    writeln!(out, "{line} | {text}").unwrap();
+    write!(out, "{:gutter$} | {:>column$}", "", "^").unwrap();
+
+    out
+}
+
+fn is_word(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+/// Index after the line comment starting at `i`.
+fn skip_line(bytes: &[u8], i: usize) -> usize {
+    bytes[i..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(bytes.len(), |p| i + p + 1)
+}
+
+/// Index after the block comment starting at `i`, Postgres allows nesting them.
+fn skip_block_comment(bytes: &[u8], mut i: usize, nested: bool) -> usize {
+    let mut depth = 0;
+
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"/*") && (nested || depth == 0) {
+            depth += 1;
+            i += 2;
+        } else if bytes[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+
+    bytes.len()
+}
+
+/// Index after the quoted literal or identifier starting at `i`.
+///
+/// The quote is escaped by doubling it, or with a backslash if `escapes`.
+fn skip_quoted(bytes: &[u8], i: usize, escapes: bool) -> usize {
+    let quote = bytes[i];
+    let mut i = i + 1;
+
+    while i < bytes.len() {
+        if escapes && bytes[i] == b'\\' {
+            i += 2;
+        } else if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+
+    bytes.len()
+}
+
+/// Index after the dollar quoted string starting at `i`, or after the `$` if it doesn't start one.
+fn skip_dollar_quoted(bytes: &[u8], i: usize) -> usize {
+    let tag_len = bytes[i + 1..].iter().take_while(|b| is_word(**b)).count();
+
+    if bytes.get(i + 1).is_some_and(u8::is_ascii_digit) || bytes.get(i + 1 + tag_len) != Some(&b'$')
+    {
+        return i + 1;
+    }
+
+    let tag = &bytes[i..i + tag_len + 2];
+    let body = i + tag.len();
+
+    bytes[body..]
+        .windows(tag.len())
+        .position(|w| w == tag)
+        .map_or(bytes.len(), |p| body + p + tag.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statements(script: &str, dialect: Dialect) -> Vec<&str> {
+        split(script, dialect).into_iter().map(|s| s.sql).collect()
+    }
+
+    #[test]
+    fn test_split_literals_and_comments() {
+        let script = "-- header; comment\n\
+            INSERT INTO t VALUES ('a;b', 'it''s', \"c;d\");\n\
+            /* block; /* nested; */ still; */ SELECT 1;\n\
+            SELECT 2";
+
+        assert_eq!(
+            statements(script, Dialect::Postgres),
+            vec![
+                "INSERT INTO t VALUES ('a;b', 'it''s', \"c;d\")",
+                "SELECT 1",
+                "SELECT 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_dollar_quotes() {
+        let script = "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql;\n\
+            DO $$ BEGIN PERFORM 1; END $$;\n\
+            SELECT a$b FROM t;";
+
+        assert_eq!(
+            statements(script, Dialect::Postgres),
+            vec![
+                "CREATE FUNCTION f() RETURNS int AS $body$ BEGIN RETURN 1; END; $body$ LANGUAGE plpgsql",
+                "DO $$ BEGIN PERFORM 1; END $$",
+                "SELECT a$b FROM t",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_trigger_bodies() {
+        let script = "BEGIN;\n\
+            CREATE TRIGGER t AFTER INSERT ON a BEGIN\n  \
+              UPDATE b SET n = CASE WHEN n > 0 THEN n + 1 ELSE 1 END;\n  \
+              DELETE FROM c;\n\
+            END;\n\
+            COMMIT;";
+
+        assert_eq!(
+            statements(script, Dialect::Sqlite),
+            vec![
+                "BEGIN",
+                "CREATE TRIGGER t AFTER INSERT ON a BEGIN\n  \
+                UPDATE b SET n = CASE WHEN n > 0 THEN n + 1 ELSE 1 END;\n  \
+                DELETE FROM c;\nEND",
+                "COMMIT",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_mysql_blocks() {
+        let script = "CREATE PROCEDURE p() BEGIN\n  \
+              IF 1 THEN SELECT 'a\\';'; END IF;\n  \
+              WHILE 0 DO SELECT 1; END WHILE;\n\
+            END;\n\
+            # comment; here\n\
+            SELECT 1;\n\
+            CREATE FUNCTION f(a INT) RETURNS INT BEGIN\n  \
+              SET a = CASE WHEN a > 0 THEN 1 END;\n  \
+              IF a THEN SET a = 2; END IF;\n  \
+              RETURN a;\n\
+            END;\n\
+            SELECT 2;";
+
+        assert_eq!(
+            statements(script, Dialect::Mysql),
+            vec![
+                "CREATE PROCEDURE p() BEGIN\n  \
+                IF 1 THEN SELECT 'a\\';'; END IF;\n  \
+                WHILE 0 DO SELECT 1; END WHILE;\nEND",
+                "SELECT 1",
+                "CREATE FUNCTION f(a INT) RETURNS INT BEGIN\n  \
+                SET a = CASE WHEN a > 0 THEN 1 END;\n  \
+                IF a THEN SET a = 2; END IF;\n  \
+                RETURN a;\nEND",
+                "SELECT 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_autocommit_reason() {
+        let reason = |sql| autocommit_reason(sql, Dialect::Postgres);
+
+        assert_eq!(
+            reason("CREATE UNIQUE INDEX CONCURRENTLY idx ON t (a)"),
+            Some("CONCURRENTLY")
+        );
+        assert_eq!(reason("drop index concurrently idx"), Some("CONCURRENTLY"));
+        assert_eq!(reason("VACUUM ANALYZE t"), Some("VACUUM"));
+        assert_eq!(reason("REFRESH MATERIALIZED VIEW CONCURRENTLY v"), None);
+        assert_eq!(reason("CREATE INDEX idx ON t (a)"), None);
+        assert_eq!(autocommit_reason("VACUUM", Dialect::Mysql), None);
+
+        assert!(adds_enum_value("ALTER TYPE mood ADD VALUE 'meh'"));
+        assert!(!adds_enum_value("ALTER TYPE mood RENAME VALUE 'a' TO 'b'"));
+    }
+
+    #[test]
+    fn test_location_and_snippet() {
+        let script = "SELECT 1;\nSELECT * FROM nope;";
+        let offset = script.find("nope").unwrap();
+
+        assert_eq!(location(script, offset), (2, 15));
+        assert_eq!(
+            snippet(script, offset, 0),
+            "  |\n2 | SELECT * FROM nope;\n  |               ^"
+        );
+        assert_eq!(
+            snippet(script, offset, 8),
+            "   |\n10 | SELECT * FROM nope;\n   |               ^"
+        );
+    }
+}